@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rust_decimal::Decimal;
+use transactions_handler::{Transaction, TransactionEngine};
+
+// Few clients with many deposits each, every deposit disputed and resolved
+// unless `resolve` is false, which leaves them all open at once. Dispute
+// lookups must not grow with the number of transactions or open disputes per
+// client.
+fn deposits_then_disputes(clients: u32, per_client: u32, resolve: bool) -> Vec<Transaction> {
+    let count = clients * per_client;
+    let deposits = (0..count).map(|tx| {
+        Transaction::create_deposit(tx % clients, tx, Decimal::from(tx % 100 + 1)).unwrap()
+    });
+    let disputes = (0..count).flat_map(move |tx| {
+        let dispute = Transaction::create_dispute(tx % clients, tx).unwrap();
+        let resolve = resolve.then(|| Transaction::create_resolve(tx % clients, tx).unwrap());
+        std::iter::once(dispute).chain(resolve)
+    });
+    deposits.chain(disputes).collect()
+}
+
+fn bench_disputes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("disputes");
+    for (per_client, resolve) in [
+        (1_000, true),
+        (10_000, true),
+        (50_000, true),
+        (50_000, false),
+    ] {
+        let transactions = deposits_then_disputes(4, per_client, resolve);
+        let name = if resolve { "resolved" } else { "open" };
+        group.bench_function(format!("{per_client} per client, {name}"), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| {
+                    let mut engine = TransactionEngine::new();
+                    for t in transactions {
+                        engine.apply(t).unwrap();
+                    }
+                    engine
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_disputes);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use transactions_handler::{checkpoint::Checkpoint, EngineConfig};
+
+// Synthetic input of `count` rows spread over `clients` clients: mostly
+// deposits and withdrawals, with some disputes later resolved or charged back.
+// A small xorshift keeps it reproducible without extra dependencies.
+fn generate_csv(count: u32, clients: u32) -> String {
+    let mut state = 0x2545_f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    let mut csv = String::from("type,client,tx,amount\n");
+    let mut deposits = vec![];
+    for tx in 1..=count {
+        let client = next() % clients + 1;
+        let amount = f64::from(next() % 100_000) / 100.0;
+        match next() % 20 {
+            0..=10 => {
+                deposits.push((client, tx));
+                writeln!(csv, "deposit,{client},{tx},{amount}").unwrap();
+            }
+            11..=16 => writeln!(csv, "withdrawal,{client},{tx},{amount}").unwrap(),
+            kind if !deposits.is_empty() => {
+                let (client, disputed) = deposits[next() as usize % deposits.len()];
+                writeln!(csv, "dispute,{client},{disputed}").unwrap();
+                let end = if kind == 19 { "chargeback" } else { "resolve" };
+                writeln!(csv, "{end},{client},{disputed}").unwrap();
+            }
+            _ => writeln!(csv, "deposit,{client},{tx},{amount}").unwrap(),
+        }
+    }
+    csv
+}
+
+fn write_input(count: u32, clients: u32) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "transactions_bench_{}_{count}_{clients}.csv",
+        std::process::id()
+    ));
+    std::fs::write(&path, generate_csv(count, clients)).unwrap();
+    path
+}
+
+fn bench_process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    group.sample_size(10);
+    for (count, clients) in [(10_000, 100), (100_000, 1_000), (100_000, 10)] {
+        let path = write_input(count, clients);
+        let config = EngineConfig::default();
+        group.throughput(Throughput::Elements(u64::from(count)));
+        group.bench_function(format!("{count} rows, {clients} clients"), |b| {
+            b.iter(|| {
+                transactions_handler::process_with_config(&config, [&path], Checkpoint::default())
+                    .unwrap()
+            })
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_process);
+criterion_main!(benches);
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, fs::File, io::BufReader, io::BufWriter, path::PathBuf};
+
+use crate::domain::Portfolio;
+use crate::error::TransactionError;
+use crate::reader::{input, read_balances};
+
+// State saved while processing a long feed: the portfolio plus the number of
+// input records already consumed.
+//
+// Resuming skips exactly `rows` records of the input, so it must be fed the
+// same input from the start. Records consumed after the last checkpoint and
+// before a crash are applied again on resume, which is safe because they never
+// reached the saved portfolio; a deposit or withdrawal appearing in both parts
+// is still caught by the duplicate tx id check.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub rows: usize,
+    pub portfolio: Portfolio,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointOptions {
+    // Save a checkpoint every N records. `None` disables checkpointing.
+    pub every: Option<usize>,
+    pub path: PathBuf,
+}
+
+impl Default for CheckpointOptions {
+    fn default() -> Self {
+        Self {
+            every: None,
+            path: PathBuf::from("checkpoint.json"),
+        }
+    }
+}
+
+// Writes to a temporary file first and renames it over `path`, so a crash
+// while saving never leaves a truncated checkpoint behind.
+pub fn save_checkpoint(checkpoint: &Checkpoint, path: &PathBuf) -> Result<(), TransactionError> {
+    let tmp = path.with_extension("json.tmp");
+    let file = File::create(&tmp)?;
+    serde_json::to_writer(BufWriter::new(file), checkpoint)
+        .map_err(|err| TransactionError::Io(err.to_string()))?;
+    Ok(fs::rename(&tmp, path)?)
+}
+
+pub fn load_checkpoint(path: &PathBuf) -> Result<Checkpoint, TransactionError> {
+    let file = File::open(path)?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| TransactionError::Parse(err.to_string()))
+}
+
+// Starts from the balances report at `path`, e.g. the output of an earlier run,
+// instead of empty accounts. A `-` reads it from stdin.
+pub fn load_balances(path: &PathBuf) -> Result<Checkpoint, TransactionError> {
+    Ok(Checkpoint {
+        rows: 0,
+        portfolio: read_balances(input(path)?)?,
+    })
+}
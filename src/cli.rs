@@ -0,0 +1,372 @@
+use log::LevelFilter;
+use std::path::PathBuf;
+
+use crate::reader::get_filenames;
+use crate::writer::{ColumnWidths, OutputFormat};
+use crate::{EngineConfig, Overdraw, WithdrawalDisputes};
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub filenames: Vec<String>,
+    pub config: EngineConfig,
+    pub resume: Option<PathBuf>,
+    // Balances report the accounts start from, see `load_balances`
+    pub balances: Option<PathBuf>,
+    // Only check the input, see `validate`
+    pub validate: bool,
+    // Most verbose level of the messages written to stderr, `warn` by default.
+    // `--verbose` raises it to at least `info`, which reports the outcome of
+    // every transaction
+    pub log_level: LevelFilter,
+}
+
+// Parses the command line:
+// `bin [--client-offset N] [--anomalies] [--scale N] [--warn-precision] [--output PATH] [--checkpoint-every N]
+//      [--resume PATH | --balances PATH] [--validate] [--log-level LEVEL] [--verbose] [--format csv|jsonl|fixed]
+//      [--freeze-rejects-disputes] [--freeze-rejects-chargebacks] [--withdrawal-disputes credit-and-hold|hold-only]
+//      [--overdraw reject|partial|allow] [--max-accounts N] <filename>...`
+// where a filename of `-` reads from stdin. The files are processed in order as
+// a single stream of transactions
+pub fn parse_args(arguments: Vec<String>) -> Result<Args, &'static str> {
+    let mut config = EngineConfig::default();
+    let (output, policy) = (&mut config.output, &mut config.policy);
+    let mut warn_precision = false;
+    let mut resume = None;
+    let mut balances = None;
+    let mut validate = false;
+    let mut log_level = LevelFilter::Warn;
+    let mut verbose = false;
+    let mut positional = vec![];
+
+    let mut iter = arguments.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--client-offset" => {
+                output.client_offset = iter
+                    .next()
+                    .ok_or("Missing value for --client-offset")?
+                    .parse()
+                    .map_err(|_| "Invalid value for --client-offset")?;
+            }
+            "--anomalies" => output.anomalies = true,
+            "--format" => {
+                output.format = match iter.next().ok_or("Missing value for --format")?.as_str() {
+                    "csv" => OutputFormat::Csv,
+                    "jsonl" => OutputFormat::JsonLines,
+                    "fixed" => OutputFormat::FixedWidth(ColumnWidths::default()),
+                    _ => return Err("Invalid value for --format"),
+                };
+            }
+            "--scale" => {
+                output.scale = iter
+                    .next()
+                    .ok_or("Missing value for --scale")?
+                    .parse()
+                    .map_err(|_| "Invalid value for --scale")?;
+            }
+            "--warn-precision" => warn_precision = true,
+            "--output" => {
+                output.path = Some(PathBuf::from(
+                    iter.next().ok_or("Missing value for --output")?,
+                ));
+            }
+            "--checkpoint-every" => {
+                config.checkpoints.every = Some(
+                    iter.next()
+                        .ok_or("Missing value for --checkpoint-every")?
+                        .parse()
+                        .map_err(|_| "Invalid value for --checkpoint-every")?,
+                );
+            }
+            "--resume" => {
+                resume = Some(PathBuf::from(
+                    iter.next().ok_or("Missing value for --resume")?,
+                ));
+            }
+            "--balances" => {
+                balances = Some(PathBuf::from(
+                    iter.next().ok_or("Missing value for --balances")?,
+                ));
+            }
+            "--validate" => validate = true,
+            "--log-level" => {
+                log_level = iter
+                    .next()
+                    .ok_or("Missing value for --log-level")?
+                    .parse()
+                    .map_err(|_| "Invalid value for --log-level")?;
+            }
+            "--verbose" => verbose = true,
+            "--freeze-rejects-disputes" => policy.freeze_rejects_disputes = true,
+            "--freeze-rejects-chargebacks" => policy.freeze_rejects_chargebacks = true,
+            "--withdrawal-disputes" => {
+                policy.withdrawal_disputes = match iter
+                    .next()
+                    .ok_or("Missing value for --withdrawal-disputes")?
+                    .as_str()
+                {
+                    "credit-and-hold" => WithdrawalDisputes::CreditAndHold,
+                    "hold-only" => WithdrawalDisputes::HoldOnly,
+                    _ => return Err("Invalid value for --withdrawal-disputes"),
+                };
+            }
+            "--overdraw" => {
+                policy.overdraw_policy =
+                    match iter.next().ok_or("Missing value for --overdraw")?.as_str() {
+                        "reject" => Overdraw::Reject,
+                        "partial" => Overdraw::Partial,
+                        "allow" => Overdraw::Allow,
+                        _ => return Err("Invalid value for --overdraw"),
+                    };
+            }
+            "--max-accounts" => {
+                policy.max_accounts = Some(
+                    iter.next()
+                        .ok_or("Missing value for --max-accounts")?
+                        .parse()
+                        .map_err(|_| "Invalid value for --max-accounts")?,
+                );
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if resume.is_some() && balances.is_some() {
+        return Err("--balances cannot be used with --resume");
+    }
+    // `--warn-precision` warns about amounts with more decimal places than the
+    // output scale, which get rounded
+    if warn_precision {
+        config.reader.warn_above_scale = Some(config.output.scale);
+    }
+    if verbose {
+        log_level = log_level.max(LevelFilter::Info);
+    }
+
+    Ok(Args {
+        filenames: get_filenames(positional)?,
+        config,
+        resume,
+        balances,
+        validate,
+        log_level,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_filename_only() {
+        let parsed = parse_args(args(&["bin", "filename.csv"])).unwrap();
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+        assert_eq!(parsed.config, EngineConfig::default());
+    }
+
+    #[test]
+    fn test_parse_many_filenames() {
+        let parsed = parse_args(args(&["bin", "day1.csv", "--anomalies", "day2.csv"])).unwrap();
+        assert_eq!(parsed.filenames, vec!["day1.csv", "day2.csv"]);
+        assert!(parsed.config.output.anomalies);
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        let parsed = parse_args(args(&["bin", "filename.csv"])).unwrap();
+        assert_eq!(parsed.log_level, LevelFilter::Warn);
+        let parsed = parse_args(args(&["bin", "--log-level", "info", "filename.csv"])).unwrap();
+        assert_eq!(parsed.log_level, LevelFilter::Info);
+        assert_eq!(
+            parse_args(args(&["bin", "--log-level", "loud", "filename.csv"])).unwrap_err(),
+            "Invalid value for --log-level"
+        );
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        let parsed = parse_args(args(&["bin", "--verbose", "filename.csv"])).unwrap();
+        assert_eq!(parsed.log_level, LevelFilter::Info);
+        let parsed = parse_args(args(&[
+            "bin",
+            "--log-level",
+            "trace",
+            "--verbose",
+            "filename.csv",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.log_level, LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let parsed = parse_args(args(&["bin", "--format", "jsonl", "filename.csv"])).unwrap();
+        assert_eq!(parsed.config.output.format, OutputFormat::JsonLines);
+        let parsed = parse_args(args(&["bin", "--format", "csv", "filename.csv"])).unwrap();
+        assert_eq!(parsed.config.output.format, OutputFormat::Csv);
+        let parsed = parse_args(args(&["bin", "--format", "fixed", "filename.csv"])).unwrap();
+        assert_eq!(
+            parsed.config.output.format,
+            OutputFormat::FixedWidth(ColumnWidths::default())
+        );
+        assert_eq!(
+            parse_args(args(&["bin", "--format", "xml", "filename.csv"])).unwrap_err(),
+            "Invalid value for --format"
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let parsed = parse_args(args(&["bin", "filename.csv"])).unwrap();
+        assert_eq!(parsed.config.policy, Policy::default());
+        let parsed = parse_args(args(&[
+            "bin",
+            "--freeze-rejects-disputes",
+            "--freeze-rejects-chargebacks",
+            "--withdrawal-disputes",
+            "hold-only",
+            "--overdraw",
+            "partial",
+            "--max-accounts",
+            "100",
+            "filename.csv",
+        ]))
+        .unwrap();
+        assert_eq!(
+            parsed.config.policy,
+            Policy {
+                freeze_rejects_disputes: true,
+                freeze_rejects_chargebacks: true,
+                withdrawal_disputes: WithdrawalDisputes::HoldOnly,
+                overdraw_policy: Overdraw::Partial,
+                max_accounts: Some(100),
+            }
+        );
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+        assert_eq!(
+            parse_args(args(&["bin", "--overdraw", "never", "filename.csv"])).unwrap_err(),
+            "Invalid value for --overdraw"
+        );
+        assert_eq!(
+            parse_args(args(&["bin", "--withdrawal-disputes", "filename.csv"])).unwrap_err(),
+            "Invalid value for --withdrawal-disputes"
+        );
+    }
+
+    #[test]
+    fn test_parse_validate() {
+        let parsed = parse_args(args(&["bin", "--validate", "filename.csv"])).unwrap();
+        assert!(parsed.validate);
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+        assert!(!parse_args(args(&["bin", "filename.csv"])).unwrap().validate);
+    }
+
+    #[test]
+    fn test_parse_client_offset() {
+        let parsed =
+            parse_args(args(&["bin", "--client-offset", "100000", "filename.csv"])).unwrap();
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+        assert_eq!(parsed.config.output.client_offset, 100000);
+    }
+
+    #[test]
+    fn test_parse_anomalies() {
+        let parsed = parse_args(args(&["bin", "--anomalies", "filename.csv"])).unwrap();
+        assert!(parsed.config.output.anomalies);
+    }
+
+    #[test]
+    fn test_parse_scale() {
+        let parsed = parse_args(args(&["bin", "--scale", "2", "filename.csv"])).unwrap();
+        assert_eq!(parsed.config.output.scale, 2);
+        assert_eq!(
+            parse_args(args(&["bin", "--scale", "two", "filename.csv"])).unwrap_err(),
+            "Invalid value for --scale"
+        );
+    }
+
+    #[test]
+    fn test_parse_warn_precision() {
+        let parsed = parse_args(args(&["bin", "filename.csv"])).unwrap();
+        assert_eq!(parsed.config.reader.warn_above_scale, None);
+        let parsed = parse_args(args(&[
+            "bin",
+            "--warn-precision",
+            "--scale",
+            "2",
+            "filename.csv",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.config.reader.warn_above_scale, Some(2));
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let parsed = parse_args(args(&["bin", "filename.csv", "--output", "out.csv"])).unwrap();
+        assert_eq!(parsed.config.output.path, Some(PathBuf::from("out.csv")));
+        assert_eq!(
+            parse_args(args(&["bin", "filename.csv"]))
+                .unwrap()
+                .config
+                .output
+                .path,
+            None
+        );
+        assert_eq!(
+            parse_args(args(&["bin", "filename.csv", "--output"])).unwrap_err(),
+            "Missing value for --output"
+        );
+    }
+
+    #[test]
+    fn test_parse_checkpoint_and_resume() {
+        let parsed = parse_args(args(&[
+            "bin",
+            "--checkpoint-every",
+            "1000",
+            "--resume",
+            "checkpoint.json",
+            "filename.csv",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.config.checkpoints.every, Some(1000));
+        assert_eq!(parsed.resume, Some(PathBuf::from("checkpoint.json")));
+    }
+
+    #[test]
+    fn test_parse_balances() {
+        let parsed =
+            parse_args(args(&["bin", "--balances", "accounts.csv", "filename.csv"])).unwrap();
+        assert_eq!(parsed.balances, Some(PathBuf::from("accounts.csv")));
+        assert_eq!(parsed.filenames, vec!["filename.csv"]);
+        assert_eq!(
+            parse_args(args(&[
+                "bin",
+                "--balances",
+                "accounts.csv",
+                "--resume",
+                "checkpoint.json",
+                "filename.csv",
+            ]))
+            .unwrap_err(),
+            "--balances cannot be used with --resume"
+        );
+    }
+
+    #[test]
+    fn test_parse_client_offset_invalid() {
+        assert_eq!(
+            parse_args(args(&["bin", "filename.csv", "--client-offset", "-1"])).unwrap_err(),
+            "Invalid value for --client-offset"
+        );
+        assert_eq!(
+            parse_args(args(&["bin", "filename.csv", "--client-offset"])).unwrap_err(),
+            "Missing value for --client-offset"
+        );
+    }
+}
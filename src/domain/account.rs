@@ -1,209 +1,982 @@
-use std::{collections::HashMap, error::Error};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::Hash;
+use std::io;
 
-use rust_decimal::Decimal;
+use log::{info, warn};
+use rust_decimal::{Decimal, RoundingStrategy};
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use super::transaction::{ClientID, Transaction, TransactionID, TransactionType};
+use crate::error::TransactionError;
 
-#[derive(Debug)]
+use super::audit::{Audit, Ledger};
+use super::owners::TxOwners;
+use super::policy::{Overdraw, Policy, WithdrawalDisputes};
+use super::stats::{ProcessingStats, Rejection};
+use super::transaction::{ClientID, Currency, Transaction, TransactionID, TransactionType};
+
+// A client has one account per currency, `None` being the implicit currency
+// of inputs without a currency column.
+type AccountKey = (ClientID, Option<Currency>);
+
+// Accounts are looked up by client and currency in O(1); `order` keeps them in
+// the order they first appeared, which is the order `snapshots` yields them in.
+// Disputes, resolves and chargebacks never open an account: disputes for an
+// account that does not exist yet wait in `pending_disputes` until it is
+// opened. Transaction ids are unique across currencies, so a dispute, resolve
+// or chargeback goes to the account its tx was applied to, whether or not the
+// row names the currency. A dispute arriving before its tx can only be matched
+// by currency though. `ledger` tracks the money entering and leaving the
+// accounts, see `audit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Portfolio {
-    accounts: Vec<Account>,
-    _pos: i32,
+    #[serde(with = "entries")]
+    accounts: HashMap<AccountKey, Account>,
+    order: Vec<AccountKey>,
+    #[serde(default, with = "entries")]
+    pending_disputes: HashMap<AccountKey, BTreeMap<TransactionID, Option<Decimal>>>,
+    tx_owners: TxOwners,
+    policy: Policy,
+    stats: ProcessingStats,
+    rejections: Vec<Rejection>,
+    #[serde(default)]
+    ledger: Ledger,
 }
 
 impl Portfolio {
-    pub fn add_transaction(&mut self, t: Transaction) -> Result<(), Box<dyn Error>> {
+    pub fn add_transaction(&mut self, t: Transaction) -> Result<(), TransactionError> {
         let client: ClientID = t.client;
+        let tx: TransactionID = t.tx;
+        self.stats.processed += 1;
 
-        for account in self.accounts.iter_mut() {
-            if account.client == client {
-                account.add_transaction(t)?;
-                return Ok(());
+        self.apply(t)
+            .inspect(|()| info!("Transaction {} of client {} accepted", tx, client))
+            .inspect_err(|reason| {
+                self.stats.rejected += 1;
+                self.rejections.push(Rejection {
+                    client,
+                    tx,
+                    reason: reason.clone(),
+                });
+            })
+    }
+
+    // A transfer is debited from the sender's account like a withdrawal and then
+    // credited to the recipient's account in the same currency, which is opened
+    // if needed.
+    fn apply(&mut self, t: Transaction) -> Result<(), TransactionError> {
+        // A transaction refused by the accounts still claims its id, as in
+        // `process_parallel`, where ids are claimed before any account is seen
+        self.tx_owners.claim(&t)?;
+        let t = self.precheck(t)?;
+        let key = (t.client, t.currency.clone());
+        if !self.accounts.contains_key(&key) && self.waits_for_account(&t)? {
+            if let TransactionType::Dispute(amount) = t.kind {
+                info!(
+                    "Client {} not found, dispute of transaction {} held until its account is opened",
+                    t.client, t.tx
+                );
+                let pending = self.pending_disputes.entry(key).or_default();
+                pending.insert(t.tx, amount);
             }
+            return Ok(());
         }
 
-        let mut account = Account::new(client);
-        account.add_transaction(t).unwrap();
-        self.accounts.push(account);
+        let kind = t.kind.clone();
+        let account = self.account_mut(key.clone());
+        let disputed = account.get_disputed_transaction(t.clone());
+        let tx = t.tx;
+        account.add_transaction(t)?;
+        match kind {
+            TransactionType::Transfer { to, amount } => {
+                self.account_mut((to, key.1)).credit(amount)?;
+            }
+            // A chargeback refused by the policy leaves the dispute open
+            TransactionType::ChargeBack => {
+                if let Some(disputed) = disputed {
+                    if self.accounts[&key].charged_back.contains(&disputed.tx) {
+                        self.ledger.charge_back(&disputed);
+                    }
+                }
+            }
+            // The kept copy has the amount actually applied, which for a
+            // partial withdrawal is less than the one requested
+            TransactionType::Deposit(_) | TransactionType::Withdraw(_) => {
+                self.ledger.record(&self.accounts[&key].transactions[&tx]);
+                let client = key.0;
+                if let Err(reason) = self.account_mut(key).open_pending_dispute(tx) {
+                    warn!(
+                        "Dispute of transaction {} of client {} rejected: {}",
+                        tx, client, reason
+                    );
+                    self.stats.rejected += 1;
+                    self.rejections.push(Rejection { client, tx, reason });
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Checks shared by `apply` and `dry_run` once the tx id is known to be
+    // free, returning `t` routed to the account it applies to.
+    fn precheck(&self, t: Transaction) -> Result<Transaction, TransactionError> {
+        self.check_capacity(&t)?;
+        self.check_recipient(&t)?;
+        let t = self.route(t);
+        self.check_disputable(&t)?;
+        Ok(t)
+    }
+
+    // Gives a dispute, resolve or chargeback of a known tx the currency of that
+    // tx, which `TxOwners::check` only lets a row omit or repeat.
+    fn route(&self, t: Transaction) -> Transaction {
+        match (&t.kind, self.tx_owners.account_of(t.tx)) {
+            (
+                TransactionType::Dispute(_)
+                | TransactionType::Resolve
+                | TransactionType::ChargeBack,
+                Some((_, currency)),
+            ) => t.in_currency(currency),
+            _ => t,
+        }
+    }
+
+    // A dispute of a claimed tx refers to one already seen. Unless its account
+    // kept it, the tx was rejected or was a transfer, and the dispute would
+    // wait forever for it.
+    fn check_disputable(&self, t: &Transaction) -> Result<(), TransactionError> {
+        if !matches!(t.kind, TransactionType::Dispute(_))
+            || self.tx_owners.account_of(t.tx).is_none()
+        {
+            return Ok(());
+        }
+        let kept = self
+            .accounts
+            .get(&(t.client, t.currency.clone()))
+            .is_some_and(|account| {
+                account.transactions.contains_key(&t.tx) || account.charged_back.contains(&t.tx)
+            });
+        if kept {
+            return Ok(());
+        }
+        warn!(
+            "Transaction {} of client {} was not applied and cannot be disputed",
+            t.tx, t.client
+        );
+        Err(TransactionError::NotDisputable(t.tx))
+    }
+
+    // Whether `t`, whose account is not open, waits for it instead of opening
+    // it. Only a dispute does; a resolve or chargeback has nothing to refer to.
+    fn waits_for_account(&self, t: &Transaction) -> Result<bool, TransactionError> {
+        match t.kind {
+            TransactionType::Dispute(_) => Ok(true),
+            TransactionType::Resolve | TransactionType::ChargeBack => {
+                warn!("Transaction {} of client {} was never seen", t.tx, t.client);
+                Err(TransactionError::UnknownTx(t.tx))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // A new account for `key`, holding the disputes buffered until it opened.
+    fn open_account(&self, key: &AccountKey) -> Account {
+        let mut account = Account::in_currency(key.0, key.1.clone());
+        account.policy = self.policy;
+        account.pending_disputes = self.pending_disputes.get(key).cloned().unwrap_or_default();
+        account
+    }
 
+    fn account_mut(&mut self, key: AccountKey) -> &mut Account {
+        if !self.accounts.contains_key(&key) {
+            let account = self.open_account(&key);
+            self.pending_disputes.remove(&key);
+            self.order.push(key.clone());
+            self.accounts.insert(key.clone(), account);
+        }
+        self.accounts.get_mut(&key).unwrap()
+    }
+
+    fn check_recipient(&self, t: &Transaction) -> Result<(), TransactionError> {
+        let (to, amount) = match t.kind {
+            TransactionType::Transfer { to, amount } => (to, amount),
+            _ => return Ok(()),
+        };
+        match self.accounts.get(&(to, t.currency.clone())) {
+            Some(account) if account.snapshot.locked => Err(TransactionError::AccountLocked),
+            Some(account) => checked_add(account.snapshot.total, amount).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    // Refuses `t` if the accounts it would open exceed `policy.max_accounts`.
+    // Disputes, resolves and chargebacks never open one.
+    fn check_capacity(&self, t: &Transaction) -> Result<(), TransactionError> {
+        let limit = match self.policy.max_accounts {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut opened = vec![];
+        match t.kind {
+            TransactionType::Deposit(_) | TransactionType::Withdraw(_) => {
+                opened.push((t.client, t.currency.clone()));
+            }
+            TransactionType::Transfer { to, .. } => {
+                opened.push((t.client, t.currency.clone()));
+                opened.push((to, t.currency.clone()));
+            }
+            _ => {}
+        }
+        opened.retain(|key| !self.accounts.contains_key(key));
+        opened.dedup();
+        if self.accounts.len() + opened.len() > limit {
+            return Err(TransactionError::TooManyAccounts(limit));
+        }
         Ok(())
     }
 
+    // Computes the snapshot the client's account would have after `t`, or the
+    // error that would reject it, without changing the portfolio.
+    pub fn dry_run(&self, t: &Transaction) -> Result<Snapshot, TransactionError> {
+        self.tx_owners.check(t)?;
+        let t = self.precheck(t.clone())?;
+        let key = (t.client, t.currency.clone());
+        let mut account = match self.accounts.get(&key) {
+            Some(account) => account.clone(),
+            None if self.waits_for_account(&t)? => return Ok(Snapshot::new(t.client, t.currency)),
+            None => self.open_account(&key),
+        };
+        let tx = t.tx;
+        account.add_transaction(t)?;
+        // A waiting dispute that cannot open is refused on its own
+        account.open_pending_dispute(tx).ok();
+        Ok(account.take_snapshot())
+    }
+
     pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    pub fn with_policy(policy: Policy) -> Self {
         Self {
-            accounts: vec![],
-            _pos: 0,
+            accounts: HashMap::new(),
+            order: vec![],
+            pending_disputes: HashMap::new(),
+            tx_owners: TxOwners::default(),
+            policy,
+            stats: ProcessingStats::default(),
+            rejections: vec![],
+            ledger: Ledger::default(),
+        }
+    }
+
+    // Applies `policy` to the accounts already open as well as to the ones
+    // opened from now on, e.g. after restoring a checkpoint.
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+        for account in self.accounts.values_mut() {
+            account.policy = policy;
+        }
+    }
+
+    // Writes the whole state as JSON: accounts with their open and pending
+    // disputes, tx owners, policy, stats and rejections.
+    pub fn save_state<W: io::Write>(&self, writer: W) -> Result<(), TransactionError> {
+        serde_json::to_writer(writer, self).map_err(|err| TransactionError::Io(err.to_string()))
+    }
+
+    // Restores a portfolio written by `save_state`. Applying the remaining
+    // transactions to it gives the same result as an uninterrupted run.
+    pub fn load_state<R: io::Read>(reader: R) -> Result<Self, TransactionError> {
+        serde_json::from_reader(reader).map_err(|err| TransactionError::Parse(err.to_string()))
+    }
+
+    // Opens an account with the balances of `snapshot`, e.g. as written by an
+    // earlier run, so a locked account keeps refusing new activity. Held funds
+    // cannot be seeded since the disputes behind them are unknown.
+    pub fn seed(&mut self, snapshot: Snapshot) -> Result<(), TransactionError> {
+        let key = (snapshot.client, snapshot.currency.clone());
+        if self.accounts.contains_key(&key) {
+            return Err(TransactionError::Invalid(format!(
+                "Client {} is seeded twice",
+                snapshot.client
+            )));
+        }
+        if snapshot.held != dec!(0) {
+            return Err(TransactionError::Invalid(format!(
+                "Cannot seed client {} with held funds",
+                snapshot.client
+            )));
+        }
+        self.ledger.seed(snapshot.total);
+        let account = self.account_mut(key);
+        account.snapshot.total = snapshot.total;
+        account.snapshot.locked = snapshot.locked;
+        Ok(())
+    }
+
+    // Counts an input row that was skipped without reaching any account.
+    pub fn skip_record(&mut self) {
+        self.stats.skipped += 1;
+    }
+
+    // Counters so far, with `pending` counting the disputes waiting right now.
+    pub fn stats(&self) -> ProcessingStats {
+        ProcessingStats {
+            pending: self.waiting_disputes().len(),
+            ..self.stats.clone()
+        }
+    }
+
+    // Disputes still waiting for their transaction or account, as client and
+    // tx id, sorted.
+    pub fn waiting_disputes(&self) -> Vec<(ClientID, TransactionID)> {
+        let buffered = self.pending_disputes.iter();
+        let kept = self
+            .accounts
+            .iter()
+            .map(|(key, account)| (key, &account.pending_disputes));
+        let mut waiting: Vec<_> = buffered
+            .chain(kept)
+            .flat_map(|((client, _), txs)| txs.keys().map(move |tx| (*client, *tx)))
+            .collect();
+        waiting.sort();
+        waiting
+    }
+
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    // Administrative end-of-cycle cleanup: every open dispute on every account is
+    // resolved (not charged back), releasing its held funds. Disputes still
+    // waiting for their transaction or account are dropped, so they never open.
+    pub fn clear_all_disputes(&mut self) -> Result<(), TransactionError> {
+        for account in self.accounts.values_mut() {
+            account.clear_disputes()?;
+        }
+        self.pending_disputes.clear();
+        Ok(())
+    }
+
+    // Folds the accounts of `other` into this portfolio, e.g. the partial result
+    // of another shard or input file. Each account and tx id must belong to only
+    // one of them, otherwise nothing is merged. The accounts of `other` come
+    // after the ones already here and its stats and rejections are added up.
+    pub fn merge(&mut self, other: Portfolio) -> Result<(), TransactionError> {
+        if let Some((client, _)) = other
+            .order
+            .iter()
+            .find(|key| self.accounts.contains_key(key))
+        {
+            return Err(TransactionError::DuplicateClient(*client));
+        }
+        self.tx_owners.merge(other.tx_owners)?;
+
+        self.accounts.extend(other.accounts);
+        self.order.extend(other.order);
+        for (key, txs) in other.pending_disputes {
+            self.pending_disputes.entry(key).or_default().extend(txs);
         }
+        let accounts = &mut self.accounts;
+        self.pending_disputes
+            .retain(|key, txs| match accounts.get_mut(key) {
+                Some(account) => {
+                    account.pending_disputes.append(txs);
+                    false
+                }
+                None => true,
+            });
+        self.stats.processed += other.stats.processed;
+        self.stats.rejected += other.stats.rejected;
+        self.stats.skipped += other.stats.skipped;
+        self.rejections.extend(other.rejections);
+        self.ledger.merge(&other.ledger);
+        Ok(())
     }
 
-    pub fn get_snapshot_line(&mut self) -> Option<Snapshot> {
-        match self.accounts.get(self._pos as usize) {
-            Some(account) => {
-                self._pos += 1;
-                Some(account.take_snapshot())
+    // Conservation-of-funds check: the accounts must add up to what was seeded
+    // and deposited, less what was withdrawn, with chargebacks taken into
+    // account. Any discrepancy is a bug in the balance arithmetic and is
+    // logged. Linear in the number of accounts, so meant to run once processing
+    // is done.
+    pub fn audit(&self) -> Result<Audit, TransactionError> {
+        let mut actual = dec!(0);
+        let mut credited_withdrawals = dec!(0);
+        for account in self.accounts.values() {
+            actual = checked_add(actual, account.snapshot.total)?;
+            if !account.holds_withdrawals_only() {
+                credited_withdrawals =
+                    checked_add(credited_withdrawals, account.held_breakdown().1)?;
             }
-            _ => None,
         }
+        let audit = Audit {
+            expected: self.ledger.expected_total(credited_withdrawals)?,
+            actual,
+        };
+        if !audit.is_balanced() {
+            warn!(
+                "Accounts hold {} but {} was expected, a discrepancy of {}",
+                audit.actual,
+                audit.expected,
+                audit.discrepancy()
+            );
+        }
+        Ok(audit)
+    }
+
+    // Current snapshot of a single client in the implicit currency.
+    pub fn get_account(&self, client: ClientID) -> Option<Snapshot> {
+        self.get_currency_account(client, None)
+    }
+
+    // Current snapshot of the account of `client` in `currency`.
+    pub fn get_currency_account(
+        &self,
+        client: ClientID,
+        currency: Option<Currency>,
+    ) -> Option<Snapshot> {
+        self.accounts
+            .get(&(client, currency))
+            .map(Account::take_snapshot)
+    }
+
+    // Ids of the clients with at least one account, sorted and each listed once
+    // however many currencies it holds.
+    pub fn client_ids(&self) -> Vec<ClientID> {
+        let mut clients: Vec<ClientID> = self.accounts.keys().map(|(client, _)| *client).collect();
+        clients.sort_unstable();
+        clients.dedup();
+        clients
+    }
+
+    // Ids of the transactions of `client` currently under dispute in the
+    // implicit currency.
+    pub fn disputed_tx_ids(&self, client: ClientID) -> Vec<TransactionID> {
+        self.accounts
+            .get(&(client, None))
+            .map(Account::disputed_tx_ids)
+            .unwrap_or_default()
+    }
+
+    pub fn get_account_report(&self, client: ClientID) -> Option<AccountReport> {
+        self.accounts.get(&(client, None)).map(|account| {
+            let (held_by_deposits, held_by_withdrawals) = account.held_breakdown();
+            AccountReport {
+                snapshot: account.take_snapshot(),
+                disputed: account.disputed_tx_ids(),
+                held_by_deposits,
+                held_by_withdrawals,
+            }
+        })
+    }
+
+    // Snapshots of every account in the order they first appeared. Borrows the
+    // portfolio, so it can be iterated any number of times.
+    pub fn snapshots(&self) -> impl Iterator<Item = Snapshot> + '_ {
+        self.order
+            .iter()
+            .map(|key| self.accounts[key].take_snapshot())
+    }
+}
+
+impl Default for Portfolio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Two portfolios are equal when they hold the same accounts, with the same
+// balances, transactions and disputes, whatever the order the clients appeared
+// in. Counters, rejections and the audit ledger describe how the accounts were
+// reached and are not compared.
+impl PartialEq for Portfolio {
+    fn eq(&self, other: &Self) -> bool {
+        self.accounts == other.accounts && self.pending_disputes == other.pending_disputes
     }
 }
 
-#[derive(Debug)]
+// Maps keyed by account are saved as lists of entries, since JSON object keys
+// can only be strings.
+mod entries {
+    use super::*;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let entries = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
+// Account holds the running snapshot of a client and only the history needed
+// to handle disputes: the deposits and withdrawals keyed by tx id, since any of
+// them may still be disputed, and the currently disputed ones. Disputes,
+// resolves and chargebacks are applied and dropped, and a charged back
+// transaction is forgotten. Dispute lookups are O(1) regardless of how many
+// transactions the client has, and open disputes are ordered by tx id so
+// anything listing them is deterministic. A dispute arriving before the
+// transaction it refers to waits in `pending_disputes` until that transaction
+// is applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Account {
     client: ClientID,
-    transactions: Vec<Transaction>,
-    disputed_transactions: HashMap<TransactionID, Transaction>,
+    transactions: HashMap<TransactionID, Transaction>,
+    disputed_transactions: BTreeMap<TransactionID, Transaction>,
+    #[serde(default)]
+    pending_disputes: BTreeMap<TransactionID, Option<Decimal>>,
+    // Charged back transactions are forgotten, only their ids are kept so a
+    // later dispute is refused instead of waiting for them forever.
+    #[serde(default)]
+    charged_back: BTreeSet<TransactionID>,
     snapshot: Snapshot,
+    policy: Policy,
 }
 
 impl Account {
-    fn add_transaction(&mut self, t: Transaction) -> Result<(), &str> {
+    fn add_transaction(&mut self, mut t: Transaction) -> Result<(), TransactionError> {
         if self.client != t.client {
-            return Err("Invalid transaction client for this account");
+            return Err(TransactionError::Invalid(String::from(
+                "Invalid transaction client for this account",
+            )));
+        }
+        if self.snapshot.currency != t.currency {
+            return Err(TransactionError::Invalid(String::from(
+                "Invalid transaction currency for this account",
+            )));
         }
 
-        self.transactions.push(t.clone());
+        // The dispute arithmetic assumes amounts are never negative. The
+        // constructors refuse them, but the fields are public.
+        if let Some(amount) = t.amount().filter(|amount| *amount < dec!(0)) {
+            return Err(TransactionError::InvalidAmount(amount));
+        }
+
+        if let TransactionType::Deposit(_)
+        | TransactionType::Withdraw(_)
+        | TransactionType::Transfer { .. } = t.kind
+        {
+            if self.snapshot.locked {
+                return Err(TransactionError::AccountLocked);
+            }
+        }
+
+        if let TransactionType::Withdraw(amount) = t.kind {
+            self.check_overdraw(&mut t, amount)?;
+        }
+        if let TransactionType::Transfer { amount, .. } = t.kind {
+            if amount > self.snapshot.get_available() {
+                return Err(TransactionError::InsufficientFunds);
+            }
+        }
 
         match t.kind {
             TransactionType::Deposit(amount) => {
-                self.snapshot.total += amount;
+                self.snapshot.total = checked_add(self.snapshot.total, amount)?;
+                self.keep(&t);
             }
             TransactionType::Withdraw(amount) => {
-                self.snapshot.total -= amount;
+                self.snapshot.total = checked_sub(self.snapshot.total, amount)?;
+                self.keep(&t);
             }
-            TransactionType::Dispute => {
-                self.open_dispute(t);
+            // Transfers are not kept, so they cannot be disputed
+            TransactionType::Transfer { amount, .. } => {
+                self.snapshot.total = checked_sub(self.snapshot.total, amount)?;
             }
-            TransactionType::ChargeBack => {
-                // If there is no dispute, ignore
-                if self.snapshot.locked {
-                    eprintln!("Cannot chargeback a locked account");
-                    return Ok(());
+            TransactionType::Dispute(_) => {
+                if self.snapshot.locked && self.policy.freeze_rejects_disputes {
+                    warn!("Cannot dispute on a locked account");
+                    return Err(TransactionError::AccountLocked);
                 }
 
-                if let Some(disp) = self.get_disputed_transaction(t) {
-                    self.apply_changeback(disp).unwrap();
+                self.open_dispute(t)?;
+            }
+            TransactionType::ChargeBack => {
+                if self.snapshot.locked && self.policy.freeze_rejects_chargebacks {
+                    warn!("Cannot chargeback a locked account");
+                    return Err(TransactionError::AccountLocked);
                 }
+
+                let disp = self
+                    .get_disputed_transaction(t.clone())
+                    .ok_or_else(|| self.not_disputed(&t))?;
+                self.apply_changeback(disp)?;
             }
             TransactionType::Resolve => {
-                // If there is no dispute, ignore
-                if let Some(disp) = self.get_disputed_transaction(t) {
-                    self.resolve(disp).unwrap();
-                }
+                let disp = self
+                    .get_disputed_transaction(t.clone())
+                    .ok_or_else(|| self.not_disputed(&t))?;
+                self.resolve(disp)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Sum of the amounts under dispute. `held` must always be equal to it.
+    fn disputed_amount(&self) -> Decimal {
+        self.disputed_transactions
+            .values()
+            .filter_map(Transaction::amount)
+            .sum()
+    }
+
+    // Amounts held by disputed deposits and by disputed withdrawals.
+    fn held_breakdown(&self) -> (Decimal, Decimal) {
+        let mut breakdown = (dec!(0), dec!(0));
+        for t in self.disputed_transactions.values() {
+            match t.kind {
+                TransactionType::Deposit(amount) => breakdown.0 += amount,
+                TransactionType::Withdraw(amount) => breakdown.1 += amount,
+                _ => {}
             }
         }
-        // self.transactions.push(t);
+        breakdown
+    }
+
+    // Keeps a deposit or withdrawal so it can be disputed later. A dispute that
+    // arrived first is opened by `open_pending_dispute`.
+    fn keep(&mut self, t: &Transaction) {
+        self.transactions.entry(t.tx).or_insert_with(|| t.clone());
+    }
+
+    // Receiving side of a transfer. Funds are accepted as long as the account is
+    // not locked and the total does not overflow, which the portfolio checks
+    // before debiting the sender.
+    fn credit(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        self.snapshot.total = checked_add(self.snapshot.total, amount)?;
         Ok(())
     }
 
+    // Recomputing the held amount is linear in the open disputes, so the
+    // invariant is only checked when a snapshot is taken.
     fn take_snapshot(&self) -> Snapshot {
+        debug_assert_eq!(self.snapshot.held, self.disputed_amount());
         self.snapshot.clone()
     }
 
-    fn apply_changeback(&mut self, disputed: Transaction) -> Result<(), &str> {
-        let amount = match disputed.kind {
-            TransactionType::Deposit(amount) => Ok(amount),
-            TransactionType::Withdraw(amount) => Ok(amount),
-            _ => Err("Only Withdraw and Deposit can be changed back"),
+    // Removing the tx from `disputed_transactions` is what prevents a repeated
+    // chargeback from applying twice; it does not rely on the locked flag.
+    // Finalizes the reversal: a charged back deposit leaves the account, a
+    // charged back withdrawal is released back to the available funds.
+    fn apply_changeback(&mut self, disputed: Transaction) -> Result<(), TransactionError> {
+        let amount = disputed.amount().ok_or_else(|| {
+            TransactionError::Invalid(String::from(
+                "Only Withdraw and Deposit can be changed back",
+            ))
+        })?;
+        match disputed.kind {
+            TransactionType::Deposit(_) => {
+                self.snapshot.total = checked_sub(self.snapshot.total, amount)?;
+            }
+            _ if self.holds_withdrawals_only() => {
+                self.snapshot.total = checked_add(self.snapshot.total, amount)?;
+            }
+            _ => {}
         }
-        .unwrap();
+        self.snapshot.held = checked_sub(self.snapshot.held, amount)?;
 
         self.disputed_transactions.remove(&disputed.tx);
-        self.snapshot.total -= amount;
-        self.snapshot.held -= amount;
+        self.transactions.remove(&disputed.tx);
+        self.charged_back.insert(disputed.tx);
         self.snapshot.locked = true;
         Ok(())
     }
 
-    fn resolve(&mut self, disputed: Transaction) -> Result<(), &str> {
-        match disputed.kind {
-            TransactionType::Deposit(amount) => {
-                self.snapshot.held -= amount;
-                self.disputed_transactions.remove(&disputed.tx);
-                Ok(())
+    // Ends the dispute restoring the balances from before it was opened: a
+    // deposit's held funds become available again and a withdrawal's provisional
+    // reversal is undone. The tx leaves `disputed_transactions`, so a later
+    // chargeback for it is a no-op.
+    fn resolve(&mut self, disputed: Transaction) -> Result<(), TransactionError> {
+        let amount = disputed.amount().ok_or_else(|| {
+            TransactionError::Invalid(String::from("Only Withdraw and Deposit can be resolved"))
+        })?;
+        if let TransactionType::Withdraw(_) = disputed.kind {
+            if !self.holds_withdrawals_only() {
+                self.snapshot.total = checked_sub(self.snapshot.total, amount)?;
             }
-            TransactionType::Withdraw(amount) => {
-                self.snapshot.held -= amount;
-                self.disputed_transactions.remove(&disputed.tx);
+        }
+        self.snapshot.held = checked_sub(self.snapshot.held, amount)?;
+
+        self.disputed_transactions.remove(&disputed.tx);
+        Ok(())
+    }
+
+    // Applies the overdraw policy to a withdrawal of `amount`, lowering the
+    // amount of `t` to the available funds when only those are withdrawn.
+    fn check_overdraw(&self, t: &mut Transaction, amount: Decimal) -> Result<(), TransactionError> {
+        let available = self.snapshot.get_available();
+        if amount <= available {
+            return Ok(());
+        }
+        match self.policy.overdraw_policy {
+            Overdraw::Reject => Err(TransactionError::InsufficientFunds),
+            Overdraw::Partial => {
+                let withdrawn = available.max(dec!(0));
+                warn!(
+                    "Withdrawal {} of client {} short by {}, only {} withdrawn",
+                    t.tx,
+                    t.client,
+                    amount - withdrawn,
+                    withdrawn
+                );
+                t.kind = TransactionType::Withdraw(withdrawn);
                 Ok(())
             }
-            _ => Err("Only Withdraw and Deposit can be changed back"),
+            Overdraw::Allow => Ok(()),
+        }
+    }
+
+    fn holds_withdrawals_only(&self) -> bool {
+        self.policy.withdrawal_disputes == WithdrawalDisputes::HoldOnly
+    }
+
+    fn disputed_tx_ids(&self) -> Vec<TransactionID> {
+        self.disputed_transactions.keys().copied().collect()
+    }
+
+    fn clear_disputes(&mut self) -> Result<(), TransactionError> {
+        for tx in self.disputed_tx_ids() {
+            let disp = self.disputed_transactions[&tx].clone();
+            self.resolve(disp)?;
         }
+        self.pending_disputes.clear();
+        debug_assert_eq!(self.snapshot.held, dec!(0));
+        Ok(())
     }
 
-    fn open_dispute(&mut self, t: Transaction) {
-        if let Some(_) = self.get_disputed_transaction(t.clone()) {
-            eprintln!("Dispute for this transaction already open. Nothing to do.");
-            return;
+    // A disputed deposit moves its amount from available to held, leaving the
+    // total unchanged. A disputed withdrawal is provisionally reversed: its
+    // amount is added back to the total but held, leaving available unchanged,
+    // unless the policy only holds it like a deposit. Either way held grows by
+    // the amount until the dispute is resolved or charged back. A dispute
+    // holding funds that would drive available below zero, because the money
+    // was already withdrawn, is refused. A partial dispute
+    // does all of this with its own amount only, which is what is kept in
+    // `disputed_transactions` in place of the original amount.
+    fn open_dispute(&mut self, t: Transaction) -> Result<(), TransactionError> {
+        if self.get_disputed_transaction(t.clone()).is_some() {
+            info!("Dispute for this transaction already open. Nothing to do.");
+            return Ok(());
+        };
+        if self.charged_back.contains(&t.tx) {
+            warn!("Transaction {} was already charged back", t.tx);
+            return Err(TransactionError::AlreadyReversed(t.tx));
+        }
+
+        let requested = match t.kind {
+            TransactionType::Dispute(requested) => requested,
+            _ => None,
+        };
+        let mut original = match self.transactions.get(&t.tx) {
+            Some(original) if original.same_id(&t) => original.clone(),
+            Some(_) => return Ok(()),
+            None => {
+                self.pending_disputes.insert(t.tx, requested);
+                return Ok(());
+            }
         };
 
-        for r in self.transactions.iter() {
-            if r == &t || r.tx != t.tx {
-                continue;
+        let full = match original.amount() {
+            Some(amount) => amount,
+            None => {
+                warn!("Invalid TX. Dispute can't be opened");
+                return Ok(());
+            }
+        };
+        let amount = requested.unwrap_or(full);
+        if amount < dec!(0) {
+            return Err(TransactionError::InvalidAmount(amount));
+        }
+        if amount > full {
+            return Err(TransactionError::DisputeExceedsAmount { tx: t.tx, amount });
+        }
+        match &mut original.kind {
+            TransactionType::Deposit(disputed) | TransactionType::Withdraw(disputed) => {
+                *disputed = amount
+            }
+            _ => {}
+        }
+        let credited =
+            matches!(original.kind, TransactionType::Withdraw(_)) && !self.holds_withdrawals_only();
+        if !credited {
+            if amount > self.snapshot.get_available() {
+                return Err(TransactionError::InsufficientFunds);
             }
+            self.snapshot.held = checked_add(self.snapshot.held, amount)?;
+        } else {
+            let total = checked_add(self.snapshot.total, amount)?;
+            self.snapshot.held = checked_add(self.snapshot.held, amount)?;
+            self.snapshot.total = total;
+        }
+        info!(
+            "Dispute of transaction {} of client {} opened, {} held",
+            t.tx, t.client, amount
+        );
+        self.disputed_transactions.insert(t.tx, original);
+        Ok(())
+    }
 
-            let original = r.clone();
+    // Opens the dispute waiting for `tx`, if any, now that it was kept. The
+    // dispute is dropped either way; an error refuses the dispute, not `tx`.
+    fn open_pending_dispute(&mut self, tx: TransactionID) -> Result<(), TransactionError> {
+        let amount = match self.pending_disputes.remove(&tx) {
+            Some(amount) => amount,
+            None => return Ok(()),
+        };
 
-            match r.kind {
-                TransactionType::Deposit(amount) => {
-                    self.snapshot.held += amount;
-                    self.disputed_transactions.insert(t.tx, original);
-                }
-                TransactionType::Withdraw(amount) => {
-                    self.snapshot.total += amount;
-                    self.snapshot.held += amount;
-                    self.disputed_transactions.insert(t.tx, original);
-                }
-                _ => {
-                    eprintln!("Invalid TX. Dispute can't be opened");
-                    continue;
-                }
-            }
+        self.open_dispute(Transaction {
+            client: self.client,
+            tx,
+            kind: TransactionType::Dispute(amount),
+            currency: self.snapshot.currency.clone(),
+        })
+    }
+
+    // Why a resolve or chargeback finds no open dispute: the transaction it
+    // refers to was never applied to this account, or it was but is not under
+    // dispute, e.g. because it was already resolved or charged back.
+    fn not_disputed(&self, t: &Transaction) -> TransactionError {
+        if self.transactions.contains_key(&t.tx) || self.charged_back.contains(&t.tx) {
+            warn!(
+                "Transaction {} of client {} is not under dispute",
+                t.tx, t.client
+            );
+            TransactionError::NotDisputed(t.tx)
+        } else {
+            warn!("Transaction {} of client {} was never seen", t.tx, t.client);
+            TransactionError::UnknownTx(t.tx)
         }
     }
 
     fn get_disputed_transaction(&self, t: Transaction) -> Option<Transaction> {
-        match self.disputed_transactions.get(&t.tx) {
-            Some(disp) => Some(disp.clone()),
-            None => None,
-        }
+        self.disputed_transactions.get(&t.tx).cloned()
     }
 
+    #[cfg(test)]
     fn new(client: ClientID) -> Self {
+        Self::in_currency(client, None)
+    }
+
+    fn in_currency(client: ClientID, currency: Option<Currency>) -> Self {
         Self {
             client,
-            transactions: vec![],
-            disputed_transactions: HashMap::new(),
-            snapshot: Snapshot::new(client),
+            transactions: HashMap::new(),
+            disputed_transactions: BTreeMap::new(),
+            pending_disputes: BTreeMap::new(),
+            charged_back: BTreeSet::new(),
+            snapshot: Snapshot::new(client, currency),
+            policy: Policy::default(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+// Balances are `Decimal`s, which panic on overflow. Any sum growing a balance
+// goes through here so an absurdly large amount is rejected instead.
+fn checked_add(balance: Decimal, amount: Decimal) -> Result<Decimal, TransactionError> {
+    balance
+        .checked_add(amount)
+        .ok_or(TransactionError::BalanceOverflow)
+}
+
+fn checked_sub(balance: Decimal, amount: Decimal) -> Result<Decimal, TransactionError> {
+    balance
+        .checked_sub(amount)
+        .ok_or(TransactionError::BalanceOverflow)
+}
+
+fn round_amount(amount: Decimal, scale: u32) -> Decimal {
+    let mut rounded = amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero);
+    rounded.rescale(scale);
+    rounded
+}
+
+// Detailed state of an account: its snapshot, the transactions currently
+// under dispute, ordered by tx id, and how much of `held` comes from disputed
+// deposits and from disputed withdrawals. The two always add up to `held`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountReport {
+    pub snapshot: Snapshot,
+    pub disputed: Vec<TransactionID>,
+    pub held_by_deposits: Decimal,
+    pub held_by_withdrawals: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Snapshot {
     pub client: ClientID,
     pub total: Decimal,
     pub held: Decimal,
     pub locked: bool,
+    #[serde(default)]
+    pub currency: Option<Currency>,
 }
 
 impl Snapshot {
-    fn new(client: ClientID) -> Self {
+    fn new(client: ClientID, currency: Option<Currency>) -> Self {
         Self {
             client,
             total: dec!(0),
             held: dec!(0),
             locked: false,
+            currency,
         }
     }
 
+    // Total minus held. Decimal arithmetic is exact, so there is no precision
+    // noise to round away. Available only becomes negative through a
+    // withdrawal under `Overdraw::Allow`: otherwise withdrawals, transfers and
+    // deposit disputes are refused when the funds are not available. A
+    // snapshot built or restored by other means may be negative too. Either
+    // way `anomalies` flags it.
     pub fn get_available(&self) -> Decimal {
         self.total - self.held
     }
+
+    // The snapshot with `total` and `held` rounded half away from zero to
+    // exactly `scale` decimal places. Available is derived from the rounded
+    // amounts, so `available + held == total` holds exactly at that scale too.
+    pub fn rounded(&self, scale: u32) -> Snapshot {
+        Snapshot {
+            total: round_amount(self.total, scale),
+            held: round_amount(self.held, scale),
+            ..self.clone()
+        }
+    }
+
+    // Flags worth an analyst's attention: a negative available balance, funds
+    // still held by open disputes, or a locked account.
+    pub fn anomalies(&self) -> Vec<&'static str> {
+        let mut flags = vec![];
+        if self.get_available() < dec!(0) {
+            flags.push("negative_available");
+        }
+        if self.held != dec!(0) {
+            flags.push("held");
+        }
+        if self.locked {
+            flags.push("locked");
+        }
+        flags
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::logger::capture::capture_logs;
     use rust_decimal_macros::dec;
 
     #[test]
@@ -226,13 +999,40 @@ mod test {
         account.add_transaction(chargeback).unwrap();
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
-        assert_eq!(s.total, dec!(32.555));
+        assert_eq!(s.total, dec!(62.555));
         assert_eq!(s.held, dec!(0));
 
-        account.add_transaction(chargeback2).unwrap();
+        assert_eq!(
+            account.add_transaction(chargeback2),
+            Err(TransactionError::NotDisputed(2))
+        );
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
-        assert_eq!(s.total, dec!(32.555));
+        assert_eq!(s.total, dec!(62.555));
+        assert_eq!(s.held, dec!(0));
+    }
+
+    #[test]
+    fn test_double_chargeback_without_lock_guard() {
+        let dep = Transaction::create_deposit(2, 1, dec!(40)).unwrap();
+        let disp = Transaction::create_dispute(2, dep.tx).unwrap();
+        let chargeback = Transaction::create_chargeback(2, dep.tx).unwrap();
+        let chargeback2 = Transaction::create_chargeback(2, dep.tx).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep).unwrap();
+        account.add_transaction(disp).unwrap();
+        account.add_transaction(chargeback).unwrap();
+        assert!(!account.disputed_transactions.contains_key(&1));
+
+        // Unlock the account: the removed dispute alone must stop a repeat
+        account.snapshot.locked = false;
+        assert_eq!(
+            account.add_transaction(chargeback2),
+            Err(TransactionError::NotDisputed(1))
+        );
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(0));
         assert_eq!(s.held, dec!(0));
     }
 
@@ -253,12 +1053,14 @@ mod test {
         assert_eq!(s.get_available(), dec!(32.555));
         assert_eq!(s.held, dec!(30));
 
+        // The reversed withdrawal is credited back to the client
         account.add_transaction(chargeback).unwrap();
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
-        assert_eq!(s.total, dec!(32.555));
+        assert_eq!(s.get_available(), dec!(32.555) + dec!(30));
         assert_eq!(s.held, dec!(0));
-        if let Some(_) = account.disputed_transactions.get(&chargeback_tx) {
+        assert!(s.locked);
+        if account.disputed_transactions.contains_key(&chargeback_tx) {
             panic!("disputedshould be removed");
         };
     }
@@ -280,12 +1082,14 @@ mod test {
         assert_eq!(s.total, dec!(15.7231));
         assert_eq!(s.held, dec!(5.7231));
 
+        // The charged back deposit leaves the account
         account.add_transaction(chargeback).unwrap();
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
-        assert_eq!(s.total, dec!(10.0000));
+        assert_eq!(s.total, dec!(15.7231) - dec!(5.7231));
+        assert!(s.locked);
         assert_eq!(s.held, dec!(0));
-        if let Some(_) = account.disputed_transactions.get(&chargeback_tx) {
+        if account.disputed_transactions.contains_key(&chargeback_tx) {
             panic!("disputedshould be removed");
         };
     }
@@ -311,12 +1115,14 @@ mod test {
         assert_eq!(s.get_available(), dec!(47.231));
         assert_eq!(s.held, dec!(10));
 
+        // The withdrawal stands: balances are back to before the dispute
         account.add_transaction(resolve).unwrap();
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
-        assert_eq!(s.get_available(), dec!(57.231));
+        assert_eq!(s.get_available(), dec!(47.231));
         assert_eq!(s.held, dec!(0));
-        if let Some(_) = account.disputed_transactions.get(&resolve_tx) {
+        assert!(!s.locked);
+        if account.disputed_transactions.contains_key(&resolve_tx) {
             panic!("disputedshould be removed");
         };
     }
@@ -342,64 +1148,1622 @@ mod test {
         assert_eq!(s.get_available(), dec!(10));
         assert_eq!(s.held, dec!(5.7231));
 
+        // Balances are back to before the dispute
         account.add_transaction(resolve).unwrap();
         let s = account.take_snapshot();
         assert_eq!(s.get_available(), s.total);
         assert_eq!(s.get_available(), dec!(15.7231));
         assert_eq!(s.held, dec!(0));
-        if let Some(_) = account.disputed_transactions.get(&resolve_tx) {
+        assert!(!s.locked);
+        if account.disputed_transactions.contains_key(&resolve_tx) {
             panic!("disputedshould be removed");
         };
     }
 
     #[test]
-    fn test_open_dispute_withdraw() {
-        let dep1 = Transaction::create_deposit(2, 1, dec!(57.2222)).unwrap();
-        let withdraw = Transaction::create_withdraw(2, 2, dec!(10)).unwrap();
-        let disp = Transaction::create_dispute(2, withdraw.tx).unwrap();
-
+    fn test_dispute_after_chargeback_is_rejected() {
         let mut account = Account::new(2);
-        account.add_transaction(dep1).unwrap();
-        account.add_transaction(withdraw).unwrap();
-        let s = account.take_snapshot();
-        assert_eq!(s.get_available(), dec!(47.2222));
-        assert_eq!(s.total, dec!(47.2222));
-        assert_eq!(s.held, dec!(0));
+        for t in [
+            Transaction::create_deposit(2, 1, dec!(40)).unwrap(),
+            Transaction::create_dispute(2, 1).unwrap(),
+            Transaction::create_chargeback(2, 1).unwrap(),
+        ] {
+            account.add_transaction(t).unwrap();
+        }
 
-        account.add_transaction(disp).unwrap();
+        assert_eq!(
+            account.add_transaction(Transaction::create_dispute(2, 1).unwrap()),
+            Err(TransactionError::AlreadyReversed(1))
+        );
         let s = account.take_snapshot();
-        assert_eq!(s.get_available(), dec!(47.2222));
-        assert_eq!(s.total, dec!(57.2222));
-        assert_eq!(s.held, dec!(10));
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(s.total, dec!(0));
+        assert!(account.pending_disputes.is_empty());
     }
 
     #[test]
-    fn test_open_dispute_deposit() {
-        let dep1 = Transaction::create_deposit(2, 1, dec!(5.72)).unwrap();
-        let dep2 = Transaction::create_deposit(2, 2, dec!(10)).unwrap();
-        let disp = Transaction::create_dispute(2, 1).unwrap();
-
+    fn test_partial_dispute_resolved() {
         let mut account = Account::new(2);
-        account.add_transaction(dep1).unwrap();
-        account.add_transaction(dep2).unwrap();
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(40)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_partial_dispute(2, 1, dec!(20)).unwrap())
+            .unwrap();
         let s = account.take_snapshot();
-        assert_eq!(s.get_available(), dec!(15.72));
+        assert_eq!(s.held, dec!(20));
+        assert_eq!(s.get_available(), dec!(20));
+        assert_eq!(s.total, dec!(40));
 
-        account.add_transaction(disp).unwrap();
+        account
+            .add_transaction(Transaction::create_resolve(2, 1).unwrap())
+            .unwrap();
         let s = account.take_snapshot();
-        assert_eq!(s.get_available(), dec!(10.00));
-        assert_eq!(s.total, dec!(15.72));
-        assert_eq!(s.held, dec!(5.72));
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(s.total, dec!(40));
+        assert!(!s.locked);
     }
 
     #[test]
-    fn test_deposit_to_account() {
-        let amount = dec!(11.01);
-        let t = Transaction::create_deposit(2, 5, amount.clone()).unwrap();
+    fn test_partial_dispute_charged_back() {
         let mut account = Account::new(2);
-        assert_eq!(account.take_snapshot().get_available(), dec!(0));
-
-        account.add_transaction(t).unwrap();
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(40)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_partial_dispute(2, 1, dec!(20)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_chargeback(2, 1).unwrap())
+            .unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(s.total, dec!(20));
+        assert!(s.locked);
+    }
+
+    #[test]
+    fn test_partial_dispute_of_withdrawal() {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(40)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_withdraw(2, 2, dec!(10)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_partial_dispute(2, 2, dec!(4)).unwrap())
+            .unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.held, dec!(4));
+        assert_eq!(s.total, dec!(34));
+
+        account
+            .add_transaction(Transaction::create_resolve(2, 2).unwrap())
+            .unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(s.total, dec!(30));
+    }
+
+    #[test]
+    fn test_partial_dispute_above_amount_is_rejected() {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(40)).unwrap())
+            .unwrap();
+        assert_eq!(
+            account.add_transaction(Transaction::create_partial_dispute(2, 1, dec!(41)).unwrap()),
+            Err(TransactionError::DisputeExceedsAmount {
+                tx: 1,
+                amount: dec!(41)
+            })
+        );
+        assert_eq!(
+            TransactionError::DisputeExceedsAmount {
+                tx: 1,
+                amount: dec!(41)
+            }
+            .to_string(),
+            "Dispute of 41 exceeds the amount of transaction 1"
+        );
+        assert_eq!(account.take_snapshot().held, dec!(0));
+        assert!(account.disputed_transactions.is_empty());
+    }
+
+    #[test]
+    fn test_pending_partial_dispute() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_partial_dispute(2, 1, dec!(15)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(40)).unwrap())
+            .unwrap();
+        let s = portfolio.get_account(2).unwrap();
+        assert_eq!(s.held, dec!(15));
+        assert_eq!(s.total, dec!(40));
+    }
+
+    #[test]
+    fn test_chargeback_after_resolve_is_rejected() {
+        let dep = Transaction::create_deposit(2, 1, dec!(20)).unwrap();
+        let disp = Transaction::create_dispute(2, dep.tx).unwrap();
+        let resolve = Transaction::create_resolve(2, dep.tx).unwrap();
+        let chargeback = Transaction::create_chargeback(2, dep.tx).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep).unwrap();
+        account.add_transaction(disp).unwrap();
+        account.add_transaction(resolve).unwrap();
+        assert!(!account.disputed_transactions.contains_key(&1));
+
+        assert_eq!(
+            account.add_transaction(chargeback),
+            Err(TransactionError::NotDisputed(1))
+        );
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(20));
+        assert_eq!(s.held, dec!(0));
+        assert!(!s.locked);
+    }
+
+    #[test]
+    fn test_dispute_again_after_resolve() {
+        let dep1 = Transaction::create_deposit(2, 1, dec!(20)).unwrap();
+        let dep2 = Transaction::create_deposit(2, 2, dec!(5)).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep1).unwrap();
+        account.add_transaction(dep2).unwrap();
+        account
+            .add_transaction(Transaction::create_dispute(2, 1).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_resolve(2, 1).unwrap())
+            .unwrap();
+
+        account
+            .add_transaction(Transaction::create_dispute(2, 1).unwrap())
+            .unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.held, dec!(20));
+        assert_eq!(s.get_available(), dec!(5));
+
+        account
+            .add_transaction(Transaction::create_chargeback(2, 1).unwrap())
+            .unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(5));
+        assert_eq!(s.held, dec!(0));
+        assert!(s.locked);
+    }
+
+    #[test]
+    fn test_resolve_and_chargeback_without_dispute() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(3, 1, dec!(8)).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_resolve(3, 1).unwrap()),
+            Err(TransactionError::NotDisputed(1))
+        );
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_chargeback(3, 1).unwrap()),
+            Err(TransactionError::NotDisputed(1))
+        );
+
+        let rejected: Vec<TransactionID> = portfolio.rejections().iter().map(|r| r.tx).collect();
+        assert_eq!(rejected, vec![1, 1]);
+        let s = portfolio.get_account(3).unwrap();
+        assert_eq!(s.total, dec!(8));
+        assert_eq!(s.held, dec!(0));
+        assert!(!s.locked);
+    }
+
+    // xorshift, so the sequences are random-looking but reproducible
+    fn pseudo_random(mut seed: u32) -> impl FnMut(u32) -> u32 {
+        move |bound: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % bound
+        }
+    }
+
+    #[test]
+    fn test_rounded_amounts_add_up() {
+        let mut next = pseudo_random(88172645);
+        for _ in 0..50 {
+            let mut portfolio = Portfolio::new();
+            for tx in 1..=100 {
+                let client = next(3);
+                let amount = Decimal::new(i64::from(next(1_000_000)), 5);
+                let t = match next(6) {
+                    0 => Transaction::create_withdraw(client, tx, amount),
+                    1 => Transaction::create_transfer(client, tx, (client + 1) % 3, amount),
+                    2 => Transaction::create_dispute(client, next(tx) + 1),
+                    3 => Transaction::create_resolve(client, next(tx) + 1),
+                    4 => Transaction::create_chargeback(client, next(tx) + 1),
+                    _ => Transaction::create_deposit(client, tx, amount),
+                };
+                portfolio.add_transaction(t.unwrap()).ok();
+            }
+
+            for s in portfolio.snapshots() {
+                assert!(s.get_available() >= dec!(0));
+                for scale in 0..=6 {
+                    let rounded = s.rounded(scale);
+                    assert_eq!(rounded.get_available() + rounded.held, rounded.total);
+                    assert_eq!(rounded.get_available().scale(), scale);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_held_matches_disputed_amount() {
+        let mut next = pseudo_random(2463534242);
+
+        for _ in 0..50 {
+            let mut account = Account::new(1);
+            for tx in 1..=20 {
+                let amount = Decimal::new(i64::from(next(10_000)), 2);
+                let t = if next(3) == 0 {
+                    Transaction::create_withdraw(1, tx, amount)
+                } else {
+                    Transaction::create_deposit(1, tx, amount)
+                };
+                account.add_transaction(t.unwrap()).ok();
+            }
+            for _ in 0..200 {
+                let tx = next(20) + 1;
+                let t = match next(5) {
+                    0 => Transaction::create_chargeback(1, tx),
+                    1 | 2 => Transaction::create_resolve(1, tx),
+                    _ => Transaction::create_dispute(1, tx),
+                };
+                account.add_transaction(t.unwrap()).ok();
+
+                let s = account.take_snapshot();
+                assert!(s.held >= dec!(0));
+                assert_eq!(s.held, account.disputed_amount());
+            }
+        }
+    }
+
+    #[test]
+    fn test_dispute_before_transaction() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_dispute(1, 5).unwrap())
+            .unwrap();
+        assert_eq!(portfolio.get_account(1), None);
+
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 5, dec!(12)).unwrap())
+            .unwrap();
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(12));
+        assert_eq!(s.held, dec!(12));
+        assert_eq!(s.get_available(), dec!(0));
+
+        portfolio
+            .add_transaction(Transaction::create_resolve(1, 5).unwrap())
+            .unwrap();
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(0));
+    }
+
+    #[test]
+    fn test_unknown_client_has_no_account() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_dispute(7, 1).unwrap())
+            .unwrap();
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_resolve(8, 1).unwrap()),
+            Err(TransactionError::UnknownTx(1))
+        );
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_chargeback(9, 1).unwrap()),
+            Err(TransactionError::UnknownTx(1))
+        );
+
+        assert_eq!(portfolio.snapshots().count(), 0);
+        assert_eq!(portfolio.get_account(7), None);
+    }
+
+    #[test]
+    fn test_dispute_of_rejected_or_transfer() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(5)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_withdraw(1, 2, dec!(8)).unwrap())
+            .unwrap_err();
+        portfolio
+            .add_transaction(Transaction::create_transfer(1, 3, 2, dec!(1)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_withdraw(3, 4, dec!(1)).unwrap())
+            .unwrap_err();
+
+        for (client, tx) in [(1, 2), (1, 3), (3, 4)] {
+            let dispute = Transaction::create_dispute(client, tx).unwrap();
+            assert_eq!(
+                portfolio.dry_run(&dispute),
+                Err(TransactionError::NotDisputable(tx))
+            );
+            assert_eq!(
+                portfolio.add_transaction(dispute),
+                Err(TransactionError::NotDisputable(tx))
+            );
+        }
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(0));
+        assert!(portfolio.pending_disputes.is_empty());
+        assert_eq!(portfolio.stats().rejected, 5);
+    }
+
+    #[test]
+    fn test_pending_dispute_refused_when_opened() {
+        let mut portfolio = Portfolio::with_policy(Policy {
+            withdrawal_disputes: WithdrawalDisputes::HoldOnly,
+            ..Default::default()
+        });
+        for t in [
+            Transaction::create_dispute(1, 2).unwrap(),
+            Transaction::create_deposit(1, 1, dec!(5)).unwrap(),
+            Transaction::create_withdraw(1, 2, dec!(5)).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        assert_eq!(
+            portfolio.rejections(),
+            [Rejection {
+                client: 1,
+                tx: 2,
+                reason: TransactionError::InsufficientFunds,
+            }]
+        );
+        let stats = portfolio.stats();
+        assert_eq!((stats.rejected, stats.pending, stats.applied()), (1, 0, 2));
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(0));
+    }
+
+    #[test]
+    fn test_merge_pending_disputes() {
+        let mut left = Portfolio::new();
+        left.add_transaction(Transaction::create_dispute(1, 1).unwrap())
+            .unwrap();
+        let mut right = Portfolio::new();
+        right
+            .add_transaction(Transaction::create_deposit(2, 2, dec!(1)).unwrap())
+            .unwrap();
+        right
+            .add_transaction(Transaction::create_dispute(2, 3).unwrap())
+            .unwrap();
+
+        left.merge(right).unwrap();
+        left.add_transaction(Transaction::create_deposit(1, 1, dec!(4)).unwrap())
+            .unwrap();
+        left.add_transaction(Transaction::create_deposit(2, 3, dec!(5)).unwrap())
+            .unwrap();
+        assert_eq!(left.get_account(1).unwrap().held, dec!(4));
+        assert_eq!(left.get_account(2).unwrap().held, dec!(5));
+    }
+
+    #[test]
+    fn test_account_report_lists_disputes() {
+        let mut portfolio = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(4, 9, dec!(3)).unwrap(),
+            Transaction::create_deposit(4, 2, dec!(5)).unwrap(),
+            Transaction::create_deposit(4, 6, dec!(1)).unwrap(),
+            Transaction::create_dispute(4, 9).unwrap(),
+            Transaction::create_dispute(4, 2).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        assert_eq!(portfolio.disputed_tx_ids(4), vec![2, 9]);
+        assert!(portfolio.disputed_tx_ids(5).is_empty());
+
+        let report = portfolio.get_account_report(4).unwrap();
+        assert_eq!(report.disputed, vec![2, 9]);
+        assert_eq!(report.snapshot.held, dec!(8));
+        assert_eq!(report.snapshot, portfolio.get_account(4).unwrap());
+    }
+
+    #[test]
+    fn test_open_dispute_withdraw() {
+        let dep1 = Transaction::create_deposit(2, 1, dec!(57.2222)).unwrap();
+        let withdraw = Transaction::create_withdraw(2, 2, dec!(10)).unwrap();
+        let disp = Transaction::create_dispute(2, withdraw.tx).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep1).unwrap();
+        account.add_transaction(withdraw).unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.get_available(), dec!(47.2222));
+        assert_eq!(s.total, dec!(47.2222));
+        assert_eq!(s.held, dec!(0));
+
+        account.add_transaction(disp).unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.get_available(), dec!(47.2222));
+        assert_eq!(s.total, dec!(57.2222));
+        assert_eq!(s.held, dec!(10));
+    }
+
+    #[test]
+    fn test_dispute_of_withdrawn_deposit_refused() {
+        let dep = Transaction::create_deposit(2, 1, dec!(10)).unwrap();
+        let withdraw = Transaction::create_withdraw(2, 2, dec!(8)).unwrap();
+        let disp = Transaction::create_dispute(2, dep.tx).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep).unwrap();
+        account.add_transaction(withdraw).unwrap();
+
+        assert_eq!(
+            account.add_transaction(disp),
+            Err(TransactionError::InsufficientFunds)
+        );
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(2));
+        assert_eq!(s.held, dec!(0));
+        assert!(account.disputed_transactions.is_empty());
+    }
+
+    #[test]
+    fn test_open_dispute_deposit() {
+        let dep1 = Transaction::create_deposit(2, 1, dec!(5.72)).unwrap();
+        let dep2 = Transaction::create_deposit(2, 2, dec!(10)).unwrap();
+        let disp = Transaction::create_dispute(2, 1).unwrap();
+
+        let mut account = Account::new(2);
+        account.add_transaction(dep1).unwrap();
+        account.add_transaction(dep2).unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.get_available(), dec!(15.72));
+
+        account.add_transaction(disp).unwrap();
+        let s = account.take_snapshot();
+        assert_eq!(s.get_available(), dec!(10.00));
+        assert_eq!(s.total, dec!(15.72));
+        assert_eq!(s.held, dec!(5.72));
+    }
+
+    // The portfolio refuses reused tx ids, but an account fed directly keeps
+    // only the first transaction with an id and disputes it once.
+    #[test]
+    fn test_dispute_of_repeated_tx_id_holds_once() {
+        let mut account = Account::new(2);
+        for t in [
+            Transaction::create_deposit(2, 1, dec!(5)).unwrap(),
+            Transaction::create_deposit(2, 1, dec!(7)).unwrap(),
+            Transaction::create_dispute(2, 1).unwrap(),
+            Transaction::create_dispute(2, 1).unwrap(),
+        ] {
+            account.add_transaction(t).unwrap();
+        }
+
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(12));
+        assert_eq!(s.held, dec!(5));
+        assert_eq!(account.disputed_tx_ids(), vec![1]);
+    }
+
+    #[test]
+    fn test_many_disputes_on_large_account() {
+        let deposits: u32 = 200_000;
+        let disputes: u32 = 20_000;
+
+        let mut account = Account::new(2);
+        for tx in 1..=deposits {
+            let t = Transaction::create_deposit(2, tx, dec!(1)).unwrap();
+            account.add_transaction(t).unwrap();
+        }
+        for tx in 1..=disputes {
+            let t = Transaction::create_dispute(2, tx).unwrap();
+            account.add_transaction(t).unwrap();
+        }
+
+        let s = account.take_snapshot();
+        assert_eq!(s.total, Decimal::from(deposits));
+        assert_eq!(s.held, Decimal::from(disputes));
+    }
+
+    #[test]
+    fn test_snapshot_serde_round_trip() {
+        for snapshot in [
+            Snapshot {
+                client: 1,
+                total: dec!(12.5),
+                held: dec!(2.25),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(0),
+                held: dec!(0),
+                locked: true,
+                currency: Some(String::from("EUR")),
+            },
+        ] {
+            let json = serde_json::to_string(&snapshot).unwrap();
+            assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_lines_follow_first_appearance() {
+        let mut portfolio = Portfolio::new();
+        for (client, tx) in [(3, 1), (1, 2), (3, 3), (2, 4)] {
+            portfolio
+                .add_transaction(Transaction::create_deposit(client, tx, dec!(1)).unwrap())
+                .unwrap();
+        }
+
+        let clients: Vec<ClientID> = portfolio.snapshots().map(|s| s.client).collect();
+        assert_eq!(clients, vec![3, 1, 2]);
+
+        // Iterating again yields the same snapshots
+        assert!(portfolio.snapshots().eq(portfolio.snapshots()));
+        assert_eq!(portfolio.snapshots().count(), 3);
+    }
+
+    #[test]
+    fn test_get_account() {
+        let mut portfolio = Portfolio::new();
+        for (client, tx) in [(1, 1), (2, 2), (1, 3)] {
+            portfolio
+                .add_transaction(Transaction::create_deposit(client, tx, dec!(1)).unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(2));
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(1));
+        assert_eq!(portfolio.get_account(3), None);
+        assert_eq!(portfolio.snapshots().next().unwrap().client, 1);
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_transfer(1, 2, 2, dec!(4)).unwrap())
+            .unwrap();
+
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(6));
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(4));
+        let clients: Vec<ClientID> = portfolio.snapshots().map(|s| s.client).collect();
+        assert_eq!(clients, vec![1, 2]);
+
+        let reused = Transaction::create_deposit(2, 2, dec!(1)).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(reused),
+            Err(TransactionError::DuplicateTxId(2))
+        );
+    }
+
+    #[test]
+    fn test_transfer_with_insufficient_funds() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_dispute(1, 1).unwrap())
+            .unwrap();
+
+        let transfer = Transaction::create_transfer(1, 2, 2, dec!(4)).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(transfer),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(10));
+        assert_eq!(portfolio.get_account(2), None);
+    }
+
+    #[test]
+    fn test_transfer_to_locked_account() {
+        let mut portfolio = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(3)).unwrap(),
+            Transaction::create_dispute(2, 2).unwrap(),
+            Transaction::create_chargeback(2, 2).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        let transfer = Transaction::create_transfer(1, 3, 2, dec!(4)).unwrap();
+        assert_eq!(
+            portfolio.dry_run(&transfer),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(
+            portfolio.add_transaction(transfer),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(10));
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(0));
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_deposit(1, 3, dec!(1)).unwrap()),
+            Err(TransactionError::DuplicateTxId(3))
+        );
+    }
+
+    #[test]
+    fn test_dry_run_check_order() {
+        let mut portfolio = Portfolio::with_policy(Policy {
+            max_accounts: Some(2),
+            ..Default::default()
+        });
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(3)).unwrap(),
+            Transaction::create_dispute(2, 2).unwrap(),
+            Transaction::create_chargeback(2, 2).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        // Client 3 would be a third account and client 2 is locked
+        let transfer = Transaction::create_transfer(3, 3, 2, dec!(1)).unwrap();
+        let expected = Err(TransactionError::TooManyAccounts(2));
+        assert_eq!(portfolio.dry_run(&transfer).map(|_| ()), expected);
+        assert_eq!(portfolio.add_transaction(transfer), expected);
+    }
+
+    #[test]
+    fn test_deposit_overflow() {
+        let mut portfolio = Portfolio::new();
+        let near_max = Decimal::MAX - dec!(1);
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, near_max).unwrap())
+            .unwrap();
+
+        let overflow = Transaction::create_deposit(1, 2, near_max).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(overflow),
+            Err(TransactionError::BalanceOverflow)
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().total, near_max);
+        // The refused deposit is not kept, so it cannot be disputed
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_dispute(1, 2).unwrap()),
+            Err(TransactionError::NotDisputable(2))
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(0));
+
+        portfolio
+            .add_transaction(Transaction::create_deposit(2, 3, near_max).unwrap())
+            .unwrap();
+        let transfer = Transaction::create_transfer(2, 4, 1, dec!(2)).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(transfer),
+            Err(TransactionError::BalanceOverflow)
+        );
+        assert_eq!(portfolio.get_account(2).unwrap().total, near_max);
+    }
+
+    #[test]
+    fn test_dispute_overflow() {
+        let near_max = Decimal::MAX - dec!(1);
+        let mut account = Account::new(1);
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_withdraw(1, 2, dec!(10)).unwrap(),
+            Transaction::create_deposit(1, 3, near_max).unwrap(),
+        ] {
+            account.add_transaction(t).unwrap();
+        }
+
+        // Reversing the withdrawal would push the total past the maximum
+        assert_eq!(
+            account.add_transaction(Transaction::create_dispute(1, 2).unwrap()),
+            Err(TransactionError::BalanceOverflow)
+        );
+        let s = account.take_snapshot();
+        assert_eq!(s.total, near_max);
+        assert_eq!(s.held, dec!(0));
+
+        assert_eq!(
+            account.credit(dec!(2)),
+            Err(TransactionError::BalanceOverflow)
+        );
+        assert_eq!(account.take_snapshot().total, near_max);
+    }
+
+    #[test]
+    fn test_accounts_per_currency() {
+        let usd = Some(String::from("USD"));
+        let eur = Some(String::from("EUR"));
+        let mut portfolio = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10))
+                .unwrap()
+                .in_currency(usd.clone()),
+            Transaction::create_deposit(1, 2, dec!(4))
+                .unwrap()
+                .in_currency(eur.clone()),
+            Transaction::create_dispute(1, 2)
+                .unwrap()
+                .in_currency(eur.clone()),
+            Transaction::create_transfer(1, 3, 2, dec!(3))
+                .unwrap()
+                .in_currency(usd.clone()),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+        // The EUR deposit cannot pay for a USD withdrawal
+        assert_eq!(
+            portfolio.add_transaction(
+                Transaction::create_withdraw(1, 4, dec!(8))
+                    .unwrap()
+                    .in_currency(usd.clone())
+            ),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        let usd_account = portfolio.get_currency_account(1, usd.clone()).unwrap();
+        assert_eq!(usd_account.total, dec!(7));
+        assert_eq!(usd_account.held, dec!(0));
+        let eur_account = portfolio.get_currency_account(1, eur.clone()).unwrap();
+        assert_eq!(eur_account.total, dec!(4));
+        assert_eq!(eur_account.held, dec!(4));
+        assert_eq!(
+            portfolio.get_currency_account(2, usd).unwrap().total,
+            dec!(3)
+        );
+        assert_eq!(portfolio.get_currency_account(2, eur), None);
+        assert_eq!(portfolio.get_account(1), None);
+        assert_eq!(portfolio.snapshots().count(), 3);
+
+        let mut state = vec![];
+        portfolio.save_state(&mut state).unwrap();
+        let resumed = Portfolio::load_state(state.as_slice()).unwrap();
+        assert_eq!(
+            resumed.snapshots().collect::<Vec<_>>(),
+            portfolio.snapshots().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_dispute_follows_currency_of_tx() {
+        let eur = Some(String::from("EUR"));
+        let mut portfolio = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10))
+                .unwrap()
+                .in_currency(eur.clone()),
+            Transaction::create_deposit(1, 2, dec!(3)).unwrap(),
+            Transaction::create_dispute(1, 1).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+        assert_eq!(
+            portfolio.get_currency_account(1, eur.clone()).unwrap().held,
+            dec!(10)
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(0));
+
+        assert_eq!(
+            portfolio.add_transaction(
+                Transaction::create_resolve(1, 1)
+                    .unwrap()
+                    .in_currency(Some(String::from("USD")))
+            ),
+            Err(TransactionError::CurrencyMismatch(1))
+        );
+        portfolio
+            .add_transaction(Transaction::create_chargeback(1, 1).unwrap())
+            .unwrap();
+        let s = portfolio.get_currency_account(1, eur).unwrap();
+        assert_eq!((s.total, s.held, s.locked), (dec!(0), dec!(0), true));
+        assert!(!portfolio.get_account(1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_seed() {
+        let mut portfolio = Portfolio::new();
+        let snapshot = Snapshot {
+            client: 1,
+            total: dec!(10),
+            held: dec!(0),
+            locked: true,
+            currency: None,
+        };
+        portfolio.seed(snapshot.clone()).unwrap();
+        assert_eq!(portfolio.get_account(1), Some(snapshot.clone()));
+
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_deposit(1, 1, dec!(5)).unwrap()),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(10));
+        assert_eq!(
+            portfolio.seed(snapshot),
+            Err(TransactionError::Invalid(String::from(
+                "Client 1 is seeded twice"
+            )))
+        );
+
+        let held = Snapshot {
+            client: 2,
+            total: dec!(10),
+            held: dec!(1),
+            locked: false,
+            currency: None,
+        };
+        assert!(portfolio.seed(held).is_err());
+        assert_eq!(portfolio.get_account(2), None);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let transactions = vec![
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(3)).unwrap(),
+            Transaction::create_dispute(1, 1).unwrap(),
+            Transaction::create_dispute(2, 4).unwrap(),
+            Transaction::create_withdraw(2, 3, dec!(5)).unwrap(),
+            Transaction::create_resolve(1, 1).unwrap(),
+            Transaction::create_deposit(2, 4, dec!(1)).unwrap(),
+            Transaction::create_dispute(1, 1).unwrap(),
+            Transaction::create_chargeback(1, 1).unwrap(),
+        ];
+
+        let mut uninterrupted = Portfolio::new();
+        for t in transactions.iter().cloned() {
+            uninterrupted.add_transaction(t).ok();
+        }
+
+        let mut first = Portfolio::new();
+        for t in transactions[..4].iter().cloned() {
+            first.add_transaction(t).ok();
+        }
+        let mut state = vec![];
+        first.save_state(&mut state).unwrap();
+        let mut resumed = Portfolio::load_state(state.as_slice()).unwrap();
+        assert_eq!(resumed.get_account_report(1), first.get_account_report(1));
+        for t in transactions[4..].iter().cloned() {
+            resumed.add_transaction(t).ok();
+        }
+
+        assert_eq!(
+            resumed.snapshots().collect::<Vec<_>>(),
+            uninterrupted.snapshots().collect::<Vec<_>>()
+        );
+        assert_eq!(resumed.stats(), uninterrupted.stats());
+        assert_eq!(resumed.rejections(), uninterrupted.rejections());
+        assert_eq!(resumed.get_account(2).unwrap().held, dec!(1));
+        assert!(matches!(
+            Portfolio::load_state("{".as_bytes()),
+            Err(TransactionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_audit_clean_run() {
+        let mut portfolio = Portfolio::new();
+        portfolio.seed(Snapshot::new(9, None)).unwrap();
+        portfolio
+            .seed(Snapshot {
+                total: dec!(7),
+                ..Snapshot::new(8, None)
+            })
+            .unwrap();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(1, 2, dec!(3)).unwrap(),
+            Transaction::create_withdraw(1, 3, dec!(4)).unwrap(),
+            Transaction::create_deposit(2, 4, dec!(20)).unwrap(),
+            Transaction::create_withdraw(2, 5, dec!(6)).unwrap(),
+            Transaction::create_transfer(2, 6, 3, dec!(5)).unwrap(),
+            Transaction::create_deposit(1, 7, dec!(2)).unwrap(),
+            Transaction::create_dispute(1, 2).unwrap(),
+            Transaction::create_chargeback(1, 2).unwrap(),
+            Transaction::create_dispute(2, 5).unwrap(),
+            Transaction::create_chargeback(2, 5).unwrap(),
+            Transaction::create_deposit(3, 8, dec!(1)).unwrap(),
+            Transaction::create_withdraw(3, 9, dec!(2)).unwrap(),
+            Transaction::create_dispute(3, 9).unwrap(),
+            Transaction::create_partial_dispute(3, 8, dec!(0.5)).unwrap(),
+            Transaction::create_dispute(1, 7).unwrap(),
+            Transaction::create_resolve(1, 7).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+        portfolio
+            .add_transaction(Transaction::create_withdraw(3, 10, dec!(100)).unwrap())
+            .unwrap_err();
+
+        let audit = portfolio.audit().unwrap();
+        assert!(audit.is_balanced());
+        assert_eq!(audit.discrepancy(), dec!(0));
+        assert_eq!(audit.actual, dec!(36));
+    }
+
+    #[test]
+    fn test_audit_detects_discrepancy() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+        portfolio.account_mut((1, None)).snapshot.total = dec!(8);
+
+        let audit = portfolio.audit().unwrap();
+        assert!(!audit.is_balanced());
+        assert_eq!(audit.discrepancy(), dec!(-2));
+    }
+
+    #[test]
+    fn test_eq_ignores_client_order() {
+        let deposits = [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(4)).unwrap(),
+            Transaction::create_deposit(3, 3, dec!(7)).unwrap(),
+        ];
+        let mut left = Portfolio::new();
+        let mut right = Portfolio::new();
+        for t in deposits.iter().cloned() {
+            left.add_transaction(t).unwrap();
+        }
+        for t in deposits.iter().rev().cloned() {
+            right.add_transaction(t).unwrap();
+        }
+        assert_ne!(
+            left.snapshots().collect::<Vec<_>>(),
+            right.snapshots().collect::<Vec<_>>()
+        );
+        assert_eq!(left, right);
+
+        let copy = left.clone();
+        left.add_transaction(Transaction::create_dispute(2, 2).unwrap())
+            .unwrap();
+        assert_ne!(left, copy);
+        right
+            .add_transaction(Transaction::create_dispute(2, 2).unwrap())
+            .unwrap();
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn test_client_ids() {
+        let mut portfolio = Portfolio::new();
+        assert!(portfolio.client_ids().is_empty());
+        for (client, tx) in [(5, 1), (2, 2), (9, 3), (5, 4)] {
+            portfolio
+                .add_transaction(Transaction::create_deposit(client, tx, dec!(1)).unwrap())
+                .unwrap();
+        }
+        portfolio
+            .add_transaction(
+                Transaction::create_deposit(2, 5, dec!(1))
+                    .unwrap()
+                    .in_currency(Some(String::from("EUR"))),
+            )
+            .unwrap();
+        // Disputes never open an account
+        portfolio
+            .add_transaction(Transaction::create_dispute(7, 6).unwrap())
+            .unwrap();
+
+        assert_eq!(portfolio.client_ids(), vec![2, 5, 9]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = Portfolio::new();
+        let mut right = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_withdraw(1, 2, dec!(20)).unwrap(),
+        ] {
+            left.add_transaction(t).ok();
+        }
+        for t in [
+            Transaction::create_deposit(2, 3, dec!(5)).unwrap(),
+            Transaction::create_dispute(2, 3).unwrap(),
+        ] {
+            right.add_transaction(t).unwrap();
+        }
+
+        left.merge(right).unwrap();
+        let clients: Vec<ClientID> = left.snapshots().map(|s| s.client).collect();
+        assert_eq!(clients, vec![1, 2]);
+        assert_eq!(left.get_account(1).unwrap().total, dec!(10));
+        assert_eq!(left.get_account(2).unwrap().held, dec!(5));
+        assert_eq!(left.stats().processed, 4);
+        assert_eq!(left.stats().rejected, 1);
+        assert_eq!(left.rejections().len(), 1);
+
+        // The merged tx ids and disputes keep working
+        assert_eq!(
+            left.add_transaction(Transaction::create_deposit(1, 3, dec!(1)).unwrap()),
+            Err(TransactionError::DuplicateTxId(3))
+        );
+        left.add_transaction(Transaction::create_resolve(2, 3).unwrap())
+            .unwrap();
+        assert_eq!(left.get_account(2).unwrap().held, dec!(0));
+    }
+
+    #[test]
+    fn test_merge_shared_client() {
+        let mut left = Portfolio::new();
+        let mut right = Portfolio::new();
+        left.add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+        right
+            .add_transaction(Transaction::create_deposit(1, 2, dec!(5)).unwrap())
+            .unwrap();
+
+        assert_eq!(left.merge(right), Err(TransactionError::DuplicateClient(1)));
+        assert_eq!(left.get_account(1).unwrap().total, dec!(10));
+
+        let mut other = Portfolio::new();
+        other
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(5)).unwrap())
+            .unwrap();
+        assert_eq!(left.merge(other), Err(TransactionError::DuplicateTxId(1)));
+        assert_eq!(left.get_account(2), None);
+    }
+
+    #[test]
+    fn test_reject_duplicate_deposit() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+
+        let duplicate = Transaction::create_deposit(1, 1, dec!(25)).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(duplicate),
+            Err(TransactionError::DuplicateTxId(1))
+        );
+
+        let mut snapshots = portfolio.snapshots();
+        let s = snapshots.next().unwrap();
+        assert_eq!(s.total, dec!(10));
+    }
+
+    #[test]
+    fn test_reject_reused_tx_id_across_types() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 5, dec!(10)).unwrap())
+            .unwrap();
+
+        let withdraw = Transaction::create_withdraw(1, 5, dec!(3)).unwrap();
+        assert_eq!(
+            portfolio.add_transaction(withdraw),
+            Err(TransactionError::DuplicateTxId(5))
+        );
+        assert_eq!(portfolio.stats().rejected, 1);
+        assert_eq!(portfolio.rejections()[0].tx, 5);
+
+        let mut snapshots = portfolio.snapshots();
+        let s = snapshots.next().unwrap();
+        assert_eq!(s.total, dec!(10));
+    }
+
+    #[test]
+    fn test_dispute_of_another_clients_tx() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(6, 1, dec!(10)).unwrap())
+            .unwrap();
+
+        for t in [
+            Transaction::create_dispute(5, 1).unwrap(),
+            Transaction::create_resolve(5, 1).unwrap(),
+            Transaction::create_chargeback(5, 1).unwrap(),
+        ] {
+            assert_eq!(
+                portfolio.add_transaction(t),
+                Err(TransactionError::ClientMismatch(1))
+            );
+        }
+
+        let mut snapshots = portfolio.snapshots();
+        let s = snapshots.next().unwrap();
+        assert_eq!(s.client, 6);
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(snapshots.next(), None);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(10)).unwrap())
+            .unwrap();
+
+        let withdraw = Transaction::create_withdraw(1, 2, dec!(4)).unwrap();
+        let projected = portfolio.dry_run(&withdraw).unwrap();
+        assert_eq!(projected.total, dec!(6));
+
+        let overdraw = Transaction::create_withdraw(1, 2, dec!(11)).unwrap();
+        assert_eq!(
+            portfolio.dry_run(&overdraw),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        let reused = Transaction::create_withdraw(1, 1, dec!(4)).unwrap();
+        assert_eq!(
+            portfolio.dry_run(&reused),
+            Err(TransactionError::DuplicateTxId(1))
+        );
+
+        assert_eq!(portfolio.stats().processed, 1);
+        let mut snapshots = portfolio.snapshots();
+        let s = snapshots.next().unwrap();
+        assert_eq!(s.total, dec!(10));
+        assert_eq!(snapshots.next(), None);
+    }
+
+    #[test]
+    fn test_dry_run_unopened_account() {
+        let mut portfolio = Portfolio::new();
+        let dispute = Transaction::create_dispute(2, 5).unwrap();
+        let projected = portfolio.dry_run(&dispute).unwrap();
+        assert_eq!(projected.client, 2);
+        assert_eq!(projected.held, dec!(0));
+        assert_eq!(portfolio.snapshots().count(), 0);
+
+        portfolio.add_transaction(dispute).unwrap();
+        let deposit = Transaction::create_deposit(2, 5, dec!(7)).unwrap();
+        let projected = portfolio.dry_run(&deposit).unwrap();
+        assert_eq!(projected.held, dec!(7));
+        assert_eq!(projected.total, dec!(7));
+
+        let resolve = Transaction::create_resolve(3, 6).unwrap();
+        assert_eq!(
+            portfolio.dry_run(&resolve),
+            Err(TransactionError::UnknownTx(6))
+        );
+        assert_eq!(portfolio.snapshots().count(), 0);
+    }
+
+    #[test]
+    fn test_clear_all_disputes() {
+        let mut portfolio = Portfolio::new();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(5)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 2, dec!(3)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_deposit(2, 3, dec!(20)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_withdraw(2, 4, dec!(4)).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_dispute(1, 1).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_dispute(1, 2).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_dispute(2, 4).unwrap())
+            .unwrap();
+        // Waiting for their transaction and for their account
+        portfolio
+            .add_transaction(Transaction::create_dispute(1, 5).unwrap())
+            .unwrap();
+        portfolio
+            .add_transaction(Transaction::create_dispute(3, 6).unwrap())
+            .unwrap();
+
+        portfolio.clear_all_disputes().unwrap();
+        for t in [
+            Transaction::create_deposit(1, 5, dec!(1)).unwrap(),
+            Transaction::create_deposit(3, 6, dec!(1)).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        for s in portfolio.snapshots() {
+            assert_eq!(s.held, dec!(0));
+            assert_eq!(s.get_available(), s.total);
+        }
+        for account in portfolio.accounts.values() {
+            assert!(account.disputed_transactions.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_disputed_tx_ids_are_ordered() {
+        let mut account = Account::new(2);
+        for tx in [7, 3, 11, 5] {
+            account
+                .add_transaction(Transaction::create_deposit(2, tx, dec!(1)).unwrap())
+                .unwrap();
+        }
+        for tx in [11, 3, 7, 5] {
+            account
+                .add_transaction(Transaction::create_dispute(2, tx).unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(account.disputed_tx_ids(), vec![3, 5, 7, 11]);
+    }
+
+    fn locked_account() -> Account {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(10)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_deposit(2, 2, dec!(4)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_dispute(2, 1).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_chargeback(2, 1).unwrap())
+            .unwrap();
+        assert!(account.take_snapshot().locked);
+        account
+    }
+
+    #[test]
+    fn test_deposit_on_locked_account() {
+        let mut account = locked_account();
+        let deposit = Transaction::create_deposit(2, 3, dec!(100)).unwrap();
+
+        assert_eq!(
+            account.add_transaction(deposit),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(account.take_snapshot().total, dec!(4));
+        assert!(!account.transactions.contains_key(&3));
+    }
+
+    #[test]
+    fn test_withdraw_on_locked_account() {
+        let mut account = locked_account();
+        let withdraw = Transaction::create_withdraw(2, 3, dec!(1)).unwrap();
+
+        assert_eq!(
+            account.add_transaction(withdraw),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(account.take_snapshot().total, dec!(4));
+    }
+
+    fn lock_then_dispute(policy: Policy) -> (Result<(), TransactionError>, Snapshot) {
+        let mut account = locked_account();
+        account.policy = policy;
+
+        let result = account.add_transaction(Transaction::create_dispute(2, 2).unwrap());
+        (result, account.take_snapshot())
+    }
+
+    #[test]
+    fn test_dispute_after_lock_processed_by_default() {
+        let (result, s) = lock_then_dispute(Policy::default());
+        assert_eq!(result, Ok(()));
+        assert_eq!(s.total, dec!(4));
+        assert_eq!(s.held, dec!(4));
+    }
+
+    #[test]
+    fn test_dispute_after_lock_rejected_when_frozen() {
+        let policy = Policy {
+            freeze_rejects_disputes: true,
+            ..Default::default()
+        };
+        let (result, s) = lock_then_dispute(policy);
+        assert_eq!(result, Err(TransactionError::AccountLocked));
+        assert_eq!(s.total, dec!(4));
+        assert_eq!(s.held, dec!(0));
+    }
+
+    #[test]
+    fn test_locked_chargeback_logs_warning() {
+        let capture = capture_logs();
+
+        let policy = Policy {
+            freeze_rejects_chargebacks: true,
+            ..Default::default()
+        };
+        let (result, _) = charge_back_two_deposits(policy);
+        assert_eq!(result, Err(TransactionError::AccountLocked));
+
+        let messages = capture.messages.lock().unwrap();
+        assert!(messages.contains(&(
+            log::Level::Warn,
+            String::from("Cannot chargeback a locked account")
+        )));
+    }
+
+    #[test]
+    fn test_decisions_are_logged() {
+        let capture = capture_logs();
+
+        let mut portfolio = Portfolio::new();
+        for t in [
+            Transaction::create_deposit(31, 1001, dec!(3)).unwrap(),
+            Transaction::create_dispute(31, 1001).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+
+        let messages = capture.messages.lock().unwrap();
+        for expected in [
+            "Transaction 1001 of client 31 accepted",
+            "Dispute of transaction 1001 of client 31 opened, 3 held",
+        ] {
+            assert!(messages.contains(&(log::Level::Info, String::from(expected))));
+        }
+    }
+
+    #[test]
+    fn test_unknown_tx_logged_apart_from_undisputed() {
+        let capture = capture_logs();
+
+        let mut account = Account::new(58);
+        account
+            .add_transaction(Transaction::create_deposit(58, 5821, dec!(3)).unwrap())
+            .unwrap();
+        assert_eq!(
+            account.add_transaction(Transaction::create_resolve(58, 5821).unwrap()),
+            Err(TransactionError::NotDisputed(5821))
+        );
+        assert_eq!(
+            account.add_transaction(Transaction::create_chargeback(58, 5822).unwrap()),
+            Err(TransactionError::UnknownTx(5822))
+        );
+        assert_eq!(
+            account.add_transaction(Transaction::create_resolve(58, 5823).unwrap()),
+            Err(TransactionError::UnknownTx(5823))
+        );
+
+        let messages = capture.messages.lock().unwrap();
+        for expected in [
+            "Transaction 5821 of client 58 is not under dispute",
+            "Transaction 5822 of client 58 was never seen",
+            "Transaction 5823 of client 58 was never seen",
+        ] {
+            assert!(messages.contains(&(log::Level::Warn, String::from(expected))));
+        }
+        assert!(!messages.contains(&(
+            log::Level::Warn,
+            String::from("Transaction 5821 of client 58 was never seen")
+        )));
+    }
+
+    // Deposits 10, withdraws 4 and disputes the withdrawal
+    fn dispute_withdrawal(withdrawal_disputes: WithdrawalDisputes) -> Portfolio {
+        let mut portfolio = Portfolio::with_policy(Policy {
+            withdrawal_disputes,
+            ..Default::default()
+        });
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_withdraw(1, 2, dec!(4)).unwrap(),
+            Transaction::create_dispute(1, 2).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+        portfolio
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_credit_and_hold() {
+        let portfolio = dispute_withdrawal(WithdrawalDisputes::CreditAndHold);
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(10));
+        assert_eq!(s.held, dec!(4));
+        assert_eq!(s.get_available(), dec!(6));
+        assert!(portfolio.audit().unwrap().is_balanced());
+
+        let mut resolved = portfolio.clone();
+        resolved
+            .add_transaction(Transaction::create_resolve(1, 2).unwrap())
+            .unwrap();
+        let s = resolved.get_account(1).unwrap();
+        assert_eq!((s.total, s.held), (dec!(6), dec!(0)));
+        assert!(resolved.audit().unwrap().is_balanced());
+
+        let mut charged_back = portfolio;
+        charged_back
+            .add_transaction(Transaction::create_chargeback(1, 2).unwrap())
+            .unwrap();
+        let s = charged_back.get_account(1).unwrap();
+        assert_eq!((s.total, s.held), (dec!(10), dec!(0)));
+        assert!(charged_back.audit().unwrap().is_balanced());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_hold_only() {
+        let portfolio = dispute_withdrawal(WithdrawalDisputes::HoldOnly);
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(6));
+        assert_eq!(s.held, dec!(4));
+        assert_eq!(s.get_available(), dec!(2));
+        assert!(portfolio.audit().unwrap().is_balanced());
+
+        let mut resolved = portfolio.clone();
+        resolved
+            .add_transaction(Transaction::create_resolve(1, 2).unwrap())
+            .unwrap();
+        let s = resolved.get_account(1).unwrap();
+        assert_eq!((s.total, s.held), (dec!(6), dec!(0)));
+        assert!(resolved.audit().unwrap().is_balanced());
+
+        let mut charged_back = portfolio;
+        charged_back
+            .add_transaction(Transaction::create_chargeback(1, 2).unwrap())
+            .unwrap();
+        let s = charged_back.get_account(1).unwrap();
+        assert_eq!((s.total, s.held), (dec!(10), dec!(0)));
+        assert!(charged_back.audit().unwrap().is_balanced());
+
+        // Only funds still available can be held
+        let mut spent = resolved;
+        spent
+            .add_transaction(Transaction::create_withdraw(1, 3, dec!(3)).unwrap())
+            .unwrap();
+        assert_eq!(
+            spent.add_transaction(Transaction::create_dispute(1, 2).unwrap()),
+            Err(TransactionError::InsufficientFunds)
+        );
+    }
+
+    // Deposits 5 and withdraws 8
+    fn overdraw(overdraw_policy: Overdraw) -> (Portfolio, Result<(), TransactionError>) {
+        let mut portfolio = Portfolio::with_policy(Policy {
+            overdraw_policy,
+            ..Default::default()
+        });
+        portfolio
+            .add_transaction(Transaction::create_deposit(85, 8501, dec!(5)).unwrap())
+            .unwrap();
+        let result =
+            portfolio.add_transaction(Transaction::create_withdraw(85, 8502, dec!(8)).unwrap());
+        (portfolio, result)
+    }
+
+    #[test]
+    fn test_overdraw_rejected() {
+        let (portfolio, result) = overdraw(Overdraw::Reject);
+        assert_eq!(result, Err(TransactionError::InsufficientFunds));
+        assert_eq!(portfolio.get_account(85).unwrap().total, dec!(5));
+    }
+
+    #[test]
+    fn test_overdraw_partial() {
+        let capture = capture_logs();
+        let (mut portfolio, result) = overdraw(Overdraw::Partial);
+        result.unwrap();
+        let s = portfolio.get_account(85).unwrap();
+        assert_eq!(s.total, dec!(0));
+        assert_eq!(s.get_available(), dec!(0));
+        assert!(capture.messages.lock().unwrap().contains(&(
+            log::Level::Warn,
+            String::from("Withdrawal 8502 of client 85 short by 3, only 5 withdrawn")
+        )));
+        assert!(portfolio.audit().unwrap().is_balanced());
+
+        // Disputing it puts back what was actually withdrawn
+        portfolio
+            .add_transaction(Transaction::create_dispute(85, 8502).unwrap())
+            .unwrap();
+        let s = portfolio.get_account(85).unwrap();
+        assert_eq!((s.total, s.held), (dec!(5), dec!(5)));
+    }
+
+    #[test]
+    fn test_overdraw_allowed() {
+        let (portfolio, result) = overdraw(Overdraw::Allow);
+        result.unwrap();
+        let s = portfolio.get_account(85).unwrap();
+        assert_eq!(s.total, dec!(-3));
+        assert_eq!(s.get_available(), dec!(-3));
+        assert_eq!(s.anomalies(), vec!["negative_available"]);
+        assert!(portfolio.audit().unwrap().is_balanced());
+    }
+
+    #[test]
+    fn test_max_accounts() {
+        let mut portfolio = Portfolio::with_policy(Policy {
+            max_accounts: Some(2),
+            ..Default::default()
+        });
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(5)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(5)).unwrap(),
+            Transaction::create_deposit(1, 3, dec!(5)).unwrap(),
+            Transaction::create_transfer(1, 4, 2, dec!(1)).unwrap(),
+        ] {
+            portfolio.add_transaction(t).unwrap();
+        }
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_deposit(3, 5, dec!(5)).unwrap()),
+            Err(TransactionError::TooManyAccounts(2))
+        );
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_transfer(1, 6, 3, dec!(1)).unwrap()),
+            Err(TransactionError::TooManyAccounts(2))
+        );
+
+        assert_eq!(portfolio.client_ids(), vec![1, 2]);
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(9));
+        // The refused tx ids were claimed all the same
+        assert_eq!(
+            portfolio.add_transaction(Transaction::create_deposit(2, 5, dec!(1)).unwrap()),
+            Err(TransactionError::DuplicateTxId(5))
+        );
+    }
+
+    fn charge_back_two_deposits(policy: Policy) -> (Result<(), TransactionError>, Account) {
+        let mut account = Account::new(2);
+        account.policy = policy;
+        for t in [
+            Transaction::create_deposit(2, 1, dec!(3)).unwrap(),
+            Transaction::create_deposit(2, 2, dec!(4)).unwrap(),
+            Transaction::create_dispute(2, 1).unwrap(),
+            Transaction::create_dispute(2, 2).unwrap(),
+            Transaction::create_chargeback(2, 1).unwrap(),
+        ] {
+            account.add_transaction(t).unwrap();
+        }
+        assert!(account.take_snapshot().locked);
+
+        let result = account.add_transaction(Transaction::create_chargeback(2, 2).unwrap());
+        (result, account)
+    }
+
+    #[test]
+    fn test_chargeback_after_lock_processed_by_default() {
+        let (result, mut account) = charge_back_two_deposits(Policy::default());
+        assert_eq!(result, Ok(()));
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(0));
+        assert_eq!(s.held, dec!(0));
+
+        assert_eq!(
+            account.add_transaction(Transaction::create_chargeback(2, 1).unwrap()),
+            Err(TransactionError::NotDisputed(1))
+        );
+    }
+
+    #[test]
+    fn test_chargeback_after_lock_rejected_when_frozen() {
+        let policy = Policy {
+            freeze_rejects_chargebacks: true,
+            ..Default::default()
+        };
+        let (result, account) = charge_back_two_deposits(policy);
+        assert_eq!(result, Err(TransactionError::AccountLocked));
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(4));
+        assert_eq!(s.held, dec!(4));
+    }
+
+    #[test]
+    fn test_deposit_to_account() {
+        let amount = dec!(11.01);
+        let t = Transaction::create_deposit(2, 5, amount).unwrap();
+        let mut account = Account::new(2);
+        assert_eq!(account.take_snapshot().get_available(), dec!(0));
+
+        account.add_transaction(t).unwrap();
         account.take_snapshot();
         assert_eq!(account.take_snapshot().get_available(), amount);
     }
@@ -407,12 +2771,64 @@ mod test {
     #[test]
     fn test_withdraw_from_account() {
         let amount = dec!(11.01);
-        let t = Transaction::create_withdraw(2, 5, amount.clone()).unwrap();
+        let t = Transaction::create_withdraw(2, 5, amount).unwrap();
         let mut account = Account::new(2);
         assert_eq!(account.take_snapshot().get_available(), dec!(0));
 
-        account.add_transaction(t).unwrap();
-        assert_eq!(account.take_snapshot().get_available(), amount * dec!(-1));
+        assert_eq!(
+            account.add_transaction(t),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(account.take_snapshot().get_available(), dec!(0));
+    }
+
+    #[test]
+    fn test_reject_overdrawing_withdraw() {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(5)).unwrap())
+            .unwrap();
+
+        let withdraw = Transaction::create_withdraw(2, 2, dec!(10)).unwrap();
+        assert_eq!(
+            account.add_transaction(withdraw),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        let s = account.take_snapshot();
+        assert_eq!(s.total, dec!(5));
+        assert_eq!(s.get_available(), dec!(5));
+        assert!(!account.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn test_negative_amount_built_by_hand() {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(5)).unwrap())
+            .unwrap();
+
+        for kind in [
+            TransactionType::Deposit(dec!(-1)),
+            TransactionType::Withdraw(dec!(-1)),
+            TransactionType::Transfer {
+                to: 3,
+                amount: dec!(-1),
+            },
+        ] {
+            let t = Transaction {
+                client: 2,
+                tx: 2,
+                kind,
+                currency: None,
+            };
+            assert_eq!(
+                account.add_transaction(t),
+                Err(TransactionError::InvalidAmount(dec!(-1)))
+            );
+        }
+        assert_eq!(account.take_snapshot().total, dec!(5));
+        assert!(!account.transactions.contains_key(&2));
     }
 
     #[test]
@@ -422,10 +2838,44 @@ mod test {
 
         assert_eq!(
             account.add_transaction(t),
-            Err("Invalid transaction client for this account")
+            Err(TransactionError::Invalid(String::from(
+                "Invalid transaction client for this account"
+            )))
         );
     }
 
+    #[test]
+    fn test_account_retains_only_disputable_transactions() {
+        let mut account = Account::new(2);
+        account
+            .add_transaction(Transaction::create_deposit(2, 1, dec!(10)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_deposit(2, 2, dec!(5)).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_withdraw(2, 3, dec!(1)).unwrap())
+            .unwrap();
+        for _ in 0..3 {
+            account
+                .add_transaction(Transaction::create_dispute(2, 1).unwrap())
+                .unwrap();
+            account
+                .add_transaction(Transaction::create_resolve(2, 1).unwrap())
+                .unwrap();
+        }
+        assert_eq!(account.transactions.len(), 3);
+
+        account
+            .add_transaction(Transaction::create_dispute(2, 2).unwrap())
+            .unwrap();
+        account
+            .add_transaction(Transaction::create_chargeback(2, 2).unwrap())
+            .unwrap();
+        assert_eq!(account.transactions.len(), 2);
+        assert!(!account.transactions.contains_key(&2));
+    }
+
     #[test]
     fn test_new_account() {
         let client = 3;
@@ -441,6 +2891,7 @@ mod test {
             total: dec!(12),
             held: dec!(5),
             locked: false,
+            currency: None,
         };
         assert_eq!(s.get_available(), s.total - s.held)
     }
@@ -0,0 +1,168 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::TransactionError;
+
+use super::transaction::{Transaction, TransactionType};
+
+// Money that entered and left the system, kept apart from the account
+// balances so the two can be checked against each other. Transfers only move
+// money between accounts and are not recorded. A chargeback takes a deposit
+// back out of the system, or returns a withdrawal to it. Amounts of every
+// currency are added up together, which still balances since each side of the
+// check sums the same amounts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Ledger {
+    seeded: Decimal,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    charged_back_deposits: Decimal,
+    charged_back_withdrawals: Decimal,
+    // The sums keep growing while balances go up and down, so they may
+    // overflow where no balance does. Nothing can be audited after that.
+    overflow: bool,
+}
+
+impl Ledger {
+    pub fn seed(&mut self, total: Decimal) {
+        add(&mut self.seeded, total, &mut self.overflow);
+    }
+
+    // Records an applied deposit or withdrawal.
+    pub fn record(&mut self, t: &Transaction) {
+        match t.kind {
+            TransactionType::Deposit(amount) => {
+                add(&mut self.deposited, amount, &mut self.overflow)
+            }
+            TransactionType::Withdraw(amount) => {
+                add(&mut self.withdrawn, amount, &mut self.overflow)
+            }
+            _ => {}
+        }
+    }
+
+    // Records the chargeback of `disputed`, carrying the amount that was
+    // disputed.
+    pub fn charge_back(&mut self, disputed: &Transaction) {
+        match disputed.kind {
+            TransactionType::Deposit(amount) => {
+                add(&mut self.charged_back_deposits, amount, &mut self.overflow)
+            }
+            TransactionType::Withdraw(amount) => add(
+                &mut self.charged_back_withdrawals,
+                amount,
+                &mut self.overflow,
+            ),
+            _ => {}
+        }
+    }
+
+    pub fn merge(&mut self, other: &Ledger) {
+        let overflow = &mut self.overflow;
+        add(&mut self.seeded, other.seeded, overflow);
+        add(&mut self.deposited, other.deposited, overflow);
+        add(&mut self.withdrawn, other.withdrawn, overflow);
+        add(
+            &mut self.charged_back_deposits,
+            other.charged_back_deposits,
+            overflow,
+        );
+        add(
+            &mut self.charged_back_withdrawals,
+            other.charged_back_withdrawals,
+            overflow,
+        );
+        *overflow |= other.overflow;
+    }
+
+    // What all the accounts should add up to. `credited_withdrawals` is the
+    // amount of the withdrawals under dispute that were provisionally credited
+    // back to their accounts.
+    pub fn expected_total(
+        &self,
+        credited_withdrawals: Decimal,
+    ) -> Result<Decimal, TransactionError> {
+        if self.overflow {
+            return Err(TransactionError::BalanceOverflow);
+        }
+        [
+            self.deposited,
+            -self.withdrawn,
+            -self.charged_back_deposits,
+            self.charged_back_withdrawals,
+            credited_withdrawals,
+        ]
+        .into_iter()
+        .try_fold(self.seeded, Decimal::checked_add)
+        .ok_or(TransactionError::BalanceOverflow)
+    }
+}
+
+fn add(sum: &mut Decimal, amount: Decimal, overflow: &mut bool) {
+    match sum.checked_add(amount) {
+        Some(total) => *sum = total,
+        None => *overflow = true,
+    }
+}
+
+// Outcome of the conservation-of-funds check: the total the accounts should
+// hold given the money that entered and left the system, and the one they
+// actually hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audit {
+    pub expected: Decimal,
+    pub actual: Decimal,
+}
+
+impl Audit {
+    pub fn is_balanced(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    // Money the accounts hold beyond the expected total, negative when some
+    // is missing.
+    pub fn discrepancy(&self) -> Decimal {
+        self.actual - self.expected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_expected_total() {
+        let mut ledger = Ledger::default();
+        ledger.seed(dec!(7));
+        ledger.record(&Transaction::create_deposit(1, 1, dec!(10)).unwrap());
+        ledger.record(&Transaction::create_withdraw(1, 2, dec!(4)).unwrap());
+        ledger.record(&Transaction::create_dispute(1, 1).unwrap());
+        ledger.charge_back(&Transaction::create_deposit(1, 1, dec!(3)).unwrap());
+        ledger.charge_back(&Transaction::create_withdraw(1, 2, dec!(1)).unwrap());
+
+        assert_eq!(ledger.expected_total(dec!(0)), Ok(dec!(11)));
+        assert_eq!(ledger.expected_total(dec!(2)), Ok(dec!(13)));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut ledger = Ledger::default();
+        ledger.record(&Transaction::create_deposit(1, 1, Decimal::MAX).unwrap());
+        ledger.record(&Transaction::create_deposit(1, 2, Decimal::MAX).unwrap());
+        assert_eq!(
+            ledger.expected_total(dec!(0)),
+            Err(TransactionError::BalanceOverflow)
+        );
+    }
+
+    #[test]
+    fn test_discrepancy() {
+        let audit = Audit {
+            expected: dec!(10),
+            actual: dec!(8),
+        };
+        assert!(!audit.is_balanced());
+        assert_eq!(audit.discrepancy(), dec!(-2));
+    }
+}
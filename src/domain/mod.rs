@@ -1,6 +1,14 @@
 pub mod account;
+pub mod audit;
+pub mod owners;
+pub mod policy;
+pub mod stats;
 pub mod transaction;
 
 pub use account::Portfolio;
-pub use account::Snapshot;
-pub use transaction::Transaction;
+pub use account::{AccountReport, Snapshot};
+pub use audit::Audit;
+pub use owners::TxOwners;
+pub use policy::{Overdraw, Policy, WithdrawalDisputes};
+pub use stats::{ProcessingStats, Rejection};
+pub use transaction::{Transaction, TransactionKind, TransactionType};
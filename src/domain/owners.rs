@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::TransactionError;
+
+use super::transaction::{ClientID, Currency, Transaction, TransactionID, TransactionType};
+
+// The client owning each deposit, withdrawal and transfer seen so far.
+// Transaction ids are globally unique, so a new transaction reusing a known id
+// is refused whatever its type, and a dispute, resolve or chargeback must come
+// from the client that owns the referenced tx. Owners only depend on the order
+// of the input, not on whether the accounts accept the transactions. The
+// currency of a tx is kept as well, only for the ones outside the implicit
+// currency, so the rows referring to it reach the account it was applied to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TxOwners {
+    owners: HashMap<TransactionID, ClientID>,
+    #[serde(default)]
+    currencies: HashMap<TransactionID, Currency>,
+}
+
+impl TxOwners {
+    pub fn check(&self, t: &Transaction) -> Result<(), TransactionError> {
+        match (&t.kind, self.owners.get(&t.tx)) {
+            (
+                TransactionType::Deposit(_)
+                | TransactionType::Withdraw(_)
+                | TransactionType::Transfer { .. },
+                Some(_),
+            ) => Err(TransactionError::DuplicateTxId(t.tx)),
+            (_, Some(&owner)) if owner != t.client => Err(TransactionError::ClientMismatch(t.tx)),
+            // A row without a currency refers to the tx in whatever currency
+            // it was in
+            (_, Some(_))
+                if t.currency.is_some() && t.currency.as_ref() != self.currencies.get(&t.tx) =>
+            {
+                Err(TransactionError::CurrencyMismatch(t.tx))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Client and currency of the account `tx` was applied to, if known.
+    pub fn account_of(&self, tx: TransactionID) -> Option<(ClientID, Option<Currency>)> {
+        self.owners
+            .get(&tx)
+            .map(|&client| (client, self.currencies.get(&tx).cloned()))
+    }
+
+    // Checks `t` and records the owner of a deposit, withdrawal or transfer.
+    pub fn claim(&mut self, t: &Transaction) -> Result<(), TransactionError> {
+        self.check(t)?;
+        if let TransactionType::Deposit(_)
+        | TransactionType::Withdraw(_)
+        | TransactionType::Transfer { .. } = t.kind
+        {
+            self.owners.insert(t.tx, t.client);
+            if let Some(currency) = &t.currency {
+                self.currencies.insert(t.tx, currency.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // Fails without changing anything when a tx id is owned by both.
+    pub fn merge(&mut self, other: TxOwners) -> Result<(), TransactionError> {
+        if let Some(tx) = other.owners.keys().find(|tx| self.owners.contains_key(tx)) {
+            return Err(TransactionError::DuplicateTxId(*tx));
+        }
+        self.owners.extend(other.owners);
+        self.currencies.extend(other.currencies);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_claim() {
+        let mut owners = TxOwners::default();
+        let deposit = Transaction::create_deposit(1, 7, dec!(1)).unwrap();
+        owners.claim(&deposit).unwrap();
+
+        assert_eq!(
+            owners.claim(&Transaction::create_withdraw(2, 7, dec!(1)).unwrap()),
+            Err(TransactionError::DuplicateTxId(7))
+        );
+        assert_eq!(
+            owners.check(&Transaction::create_dispute(2, 7).unwrap()),
+            Err(TransactionError::ClientMismatch(7))
+        );
+        assert!(owners
+            .check(&Transaction::create_dispute(1, 7).unwrap())
+            .is_ok());
+        assert_eq!(owners.account_of(7), Some((1, None)));
+        assert_eq!(owners.account_of(8), None);
+    }
+
+    #[test]
+    fn test_claim_in_currency() {
+        let eur = Some(String::from("EUR"));
+        let mut owners = TxOwners::default();
+        owners
+            .claim(
+                &Transaction::create_deposit(1, 7, dec!(1))
+                    .unwrap()
+                    .in_currency(eur.clone()),
+            )
+            .unwrap();
+
+        assert_eq!(owners.account_of(7), Some((1, eur.clone())));
+        assert!(owners
+            .check(&Transaction::create_dispute(1, 7).unwrap())
+            .is_ok());
+        assert!(owners
+            .check(&Transaction::create_dispute(1, 7).unwrap().in_currency(eur))
+            .is_ok());
+        assert_eq!(
+            owners.check(
+                &Transaction::create_dispute(1, 7)
+                    .unwrap()
+                    .in_currency(Some(String::from("USD")))
+            ),
+            Err(TransactionError::CurrencyMismatch(7))
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+// Policy gathers the business rules that differ between deployments.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+    // When set, a locked (charged back) account refuses new disputes with
+    // `AccountLocked`.
+    // Otherwise disputes on other transactions are still opened.
+    pub freeze_rejects_disputes: bool,
+    // When set, a locked account refuses chargebacks with `AccountLocked`.
+    // Otherwise a chargeback still applies to its own disputed transaction,
+    // only the repeated chargeback of the same transaction is refused.
+    pub freeze_rejects_chargebacks: bool,
+    // How a disputed withdrawal affects the balances, see `WithdrawalDisputes`.
+    #[serde(default)]
+    pub withdrawal_disputes: WithdrawalDisputes,
+    // What a withdrawal of more than the available funds does, see `Overdraw`.
+    #[serde(default)]
+    pub overdraw_policy: Overdraw,
+    // Most accounts kept in memory, for untrusted inputs. A transaction that
+    // would open one more is refused. A client has one account per currency.
+    #[serde(default)]
+    pub max_accounts: Option<usize>,
+}
+
+// Handling of a withdrawal larger than the available funds. Transfers are
+// always refused in that case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overdraw {
+    // The withdrawal is refused with `InsufficientFunds`.
+    #[default]
+    Reject,
+    // Only the available funds are withdrawn and the shortfall is logged. A
+    // later dispute of the withdrawal is on the amount actually withdrawn.
+    Partial,
+    // The whole amount is withdrawn, leaving the available funds negative.
+    Allow,
+}
+
+// What disputing a withdrawal does to the account. Either way `held` grows by
+// the disputed amount until the dispute ends, and a chargeback leaves the
+// account with the withdrawal reversed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WithdrawalDisputes {
+    // The withdrawal is provisionally reversed: its amount is credited back to
+    // the total and held, so the available funds do not change. Resolving
+    // takes the credit back, a chargeback releases it to the available funds.
+    #[default]
+    CreditAndHold,
+    // The amount is held out of the available funds without crediting the
+    // total, which requires the client to still have it available. Resolving
+    // releases it, a chargeback credits it back to the total.
+    HoldOnly,
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::TransactionError;
+
+use super::transaction::{ClientID, TransactionID};
+
+// Counters collected while transactions are applied to a Portfolio. `skipped`
+// counts the input rows that could not be turned into a transaction at all,
+// `pending` the accepted disputes still waiting for their transaction.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingStats {
+    pub processed: usize,
+    pub rejected: usize,
+    #[serde(default)]
+    pub skipped: usize,
+    #[serde(default)]
+    pub pending: usize,
+}
+
+impl ProcessingStats {
+    // Transactions that took effect: neither rejected nor still pending
+    pub fn applied(&self) -> usize {
+        self.processed - self.rejected - self.pending
+    }
+}
+
+impl fmt::Display for ProcessingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} transactions processed: {} applied, ",
+            self.processed,
+            self.applied()
+        )?;
+        if self.pending > 0 {
+            write!(f, "{} pending, ", self.pending)?;
+        }
+        write!(
+            f,
+            "{} rejected; {} rows skipped",
+            self.rejected, self.skipped
+        )
+    }
+}
+
+// A transaction the Portfolio refused to apply, and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rejection {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub reason: TransactionError,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let stats = ProcessingStats {
+            processed: 10,
+            rejected: 3,
+            skipped: 2,
+            pending: 0,
+        };
+        assert_eq!(stats.applied(), 7);
+        assert_eq!(
+            stats.to_string(),
+            "10 transactions processed: 7 applied, 3 rejected; 2 rows skipped"
+        );
+
+        let stats = ProcessingStats {
+            pending: 2,
+            ..stats
+        };
+        assert_eq!(stats.applied(), 5);
+        assert_eq!(
+            stats.to_string(),
+            "10 transactions processed: 5 applied, 2 pending, 3 rejected; 2 rows skipped"
+        );
+    }
+}
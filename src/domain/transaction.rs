@@ -1,39 +1,159 @@
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 
-pub type ClientID = u16;
+use crate::error::TransactionError;
+
+pub type ClientID = u32;
 pub type TransactionID = u32;
+// Currency code as found in the input, e.g. `USD`
+pub type Currency = String;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
     Withdraw(Decimal),
     Deposit(Decimal),
+    // Disputes the given amount of the transaction, or all of it when `None`
+    Dispute(Option<Decimal>),
+    Resolve,
+    ChargeBack,
+    Transfer { to: ClientID, amount: Decimal },
+}
+
+// The kinds of transaction found in the input, before their fields are read.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransactionKind {
+    Deposit,
+    Withdraw,
     Dispute,
     Resolve,
     ChargeBack,
+    Transfer,
+}
+
+impl TransactionKind {
+    // Parses a kind as found in the input, ignoring case and accepting
+    // `withdraw` for withdrawals.
+    pub fn parse(kind: &str) -> Result<Self, TransactionError> {
+        match kind.to_lowercase().as_str() {
+            "deposit" => Ok(Self::Deposit),
+            "withdrawal" | "withdraw" => Ok(Self::Withdraw),
+            "dispute" => Ok(Self::Dispute),
+            "resolve" => Ok(Self::Resolve),
+            "chargeback" => Ok(Self::ChargeBack),
+            "transfer" => Ok(Self::Transfer),
+            _ => Err(TransactionError::UnknownTransactionType(String::from(kind))),
+        }
+    }
 }
 
-// Transaction is a financial transaction representation
-#[derive(Debug, Clone, PartialEq)]
+impl TransactionType {
+    // Parses the kind of a transaction as found in the input, ignoring case and
+    // accepting `withdraw` for withdrawals. Deposits, withdrawals and transfers
+    // require an amount and a dispute may carry the part of the transaction it
+    // disputes. Resolves and chargebacks refer to an earlier transaction and must
+    // not carry one. Only transfers have a destination client.
+    pub fn parse(
+        kind: &str,
+        amount: Option<Decimal>,
+        to: Option<ClientID>,
+    ) -> Result<Self, TransactionError> {
+        let required = amount.ok_or(TransactionError::MissingAmount);
+        match TransactionKind::parse(kind)? {
+            TransactionKind::Resolve | TransactionKind::ChargeBack if amount.is_some() => {
+                Err(TransactionError::UnexpectedAmount)
+            }
+            TransactionKind::Transfer => Ok(Self::Transfer {
+                to: to.ok_or(TransactionError::MissingDestination)?,
+                amount: required?,
+            }),
+            _ if to.is_some() => Err(TransactionError::UnexpectedDestination),
+            TransactionKind::Deposit => Ok(Self::Deposit(required?)),
+            TransactionKind::Withdraw => Ok(Self::Withdraw(required?)),
+            TransactionKind::Dispute => Ok(Self::Dispute(amount)),
+            TransactionKind::Resolve => Ok(Self::Resolve),
+            TransactionKind::ChargeBack => Ok(Self::ChargeBack),
+        }
+    }
+}
+
+// Transaction is a financial transaction representation. A client has a
+// separate account per currency; transactions without one use the client's
+// account in the implicit currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
     pub client: ClientID,
     pub kind: TransactionType,
     pub tx: TransactionID,
+    #[serde(default)]
+    pub currency: Option<Currency>,
 }
 
 impl Transaction {
+    // Identity comparison on (client, tx) only, ignoring the kind and amount.
+    // Use this for lookups and dedup; `==` compares the full value.
+    pub fn same_id(&self, other: &Transaction) -> bool {
+        self.client == other.client && self.tx == other.tx
+    }
+
+    // The same transaction applied to the client's account in `currency`.
+    pub fn in_currency(self, currency: Option<Currency>) -> Self {
+        Self { currency, ..self }
+    }
+
+    // Amount moved by a deposit, withdrawal or transfer. Disputes, resolves and
+    // chargebacks only refer to an earlier transaction and have none.
+    pub fn amount(&self) -> Option<Decimal> {
+        match self.kind {
+            TransactionType::Deposit(amount)
+            | TransactionType::Withdraw(amount)
+            | TransactionType::Transfer { amount, .. } => Some(amount),
+            TransactionType::Dispute(_)
+            | TransactionType::Resolve
+            | TransactionType::ChargeBack => None,
+        }
+    }
+
+    // Builds a transaction from its textual kind as found in the input, see
+    // `TransactionType::parse`.
+    pub fn create(
+        kind: &str,
+        client: ClientID,
+        tx: TransactionID,
+        amount: Option<Decimal>,
+        to: Option<ClientID>,
+    ) -> Result<Self, TransactionError> {
+        match TransactionType::parse(kind, amount, to)? {
+            TransactionType::Deposit(amount) => Self::create_deposit(client, tx, amount),
+            TransactionType::Withdraw(amount) => Self::create_withdraw(client, tx, amount),
+            TransactionType::Transfer { to, amount } => {
+                Self::create_transfer(client, tx, to, amount)
+            }
+            TransactionType::Dispute(Some(amount)) => {
+                Self::create_partial_dispute(client, tx, amount)
+            }
+            kind => Ok(Self {
+                client,
+                tx,
+                kind,
+                currency: None,
+            }),
+        }
+    }
+
     pub fn create_deposit(
         client: ClientID,
         tx: TransactionID,
         amount: Decimal,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, TransactionError> {
         if amount < dec!(0) {
-            return Err("Amount must be positive");
+            return Err(TransactionError::InvalidAmount(amount));
         }
         Ok(Self {
             client,
             tx,
             kind: TransactionType::Deposit(amount),
+            currency: None,
         })
     }
 
@@ -41,38 +161,86 @@ impl Transaction {
         client: ClientID,
         tx: TransactionID,
         amount: Decimal,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, TransactionError> {
         if amount < dec!(0) {
-            return Err("Amount must be positive");
+            return Err(TransactionError::InvalidAmount(amount));
         }
         Ok(Self {
             client,
             tx,
             kind: TransactionType::Withdraw(amount),
+            currency: None,
         })
     }
 
-    pub fn create_dispute(client: ClientID, tx: TransactionID) -> Result<Self, &'static str> {
+    // Moves `amount` from `client` to the `to` client in a single transaction.
+    pub fn create_transfer(
+        client: ClientID,
+        tx: TransactionID,
+        to: ClientID,
+        amount: Decimal,
+    ) -> Result<Self, TransactionError> {
+        if amount < dec!(0) {
+            return Err(TransactionError::InvalidAmount(amount));
+        }
+        if to == client {
+            return Err(TransactionError::Invalid(String::from(
+                "Cannot transfer to the same client",
+            )));
+        }
         Ok(Self {
             client,
             tx,
-            kind: TransactionType::Dispute,
+            kind: TransactionType::Transfer { to, amount },
+            currency: None,
         })
     }
 
-    pub fn create_resolve(client: ClientID, tx: TransactionID) -> Result<Self, &'static str> {
+    pub fn create_dispute(client: ClientID, tx: TransactionID) -> Result<Self, TransactionError> {
+        Ok(Self {
+            client,
+            tx,
+            kind: TransactionType::Dispute(None),
+            currency: None,
+        })
+    }
+
+    // Disputes only `amount` of the transaction, which must not be more than
+    // the transaction's own amount when the dispute is opened.
+    pub fn create_partial_dispute(
+        client: ClientID,
+        tx: TransactionID,
+        amount: Decimal,
+    ) -> Result<Self, TransactionError> {
+        if amount <= dec!(0) {
+            return Err(TransactionError::InvalidAmount(amount));
+        }
+        Ok(Self {
+            client,
+            tx,
+            kind: TransactionType::Dispute(Some(amount)),
+            currency: None,
+        })
+    }
+
+    pub fn create_resolve(client: ClientID, tx: TransactionID) -> Result<Self, TransactionError> {
         Ok(Self {
             client,
             tx,
             kind: TransactionType::Resolve,
+            currency: None,
         })
     }
 
-    pub fn create_chargeback(client: ClientID, tx: TransactionID) -> Result<Self, &'static str> {
+    pub fn create_chargeback(
+        client: ClientID,
+        tx: TransactionID,
+    ) -> Result<Self, TransactionError> {
         Ok(Self {
             client,
             tx,
             kind: TransactionType::ChargeBack,
+            currency: None,
         })
     }
 }
@@ -92,10 +260,176 @@ mod test {
             client,
             kind: kind.clone(),
             tx,
+            currency: None,
         };
 
         assert_eq!(t.client, client);
         assert_eq!(t.kind, kind);
         assert_eq!(t.tx, tx);
     }
+
+    #[test]
+    fn test_transaction_kind_aliases() {
+        assert_eq!(
+            TransactionKind::parse("WITHDRAW"),
+            Ok(TransactionKind::Withdraw)
+        );
+        assert_eq!(
+            TransactionKind::parse("Withdrawal"),
+            Ok(TransactionKind::Withdraw)
+        );
+        assert_eq!(
+            TransactionKind::parse("chargeBack"),
+            Ok(TransactionKind::ChargeBack)
+        );
+        assert_eq!(
+            TransactionKind::parse("refund"),
+            Err(TransactionError::UnknownTransactionType(String::from(
+                "refund"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_parse_kind_ignores_case() {
+        assert_eq!(
+            TransactionType::parse("Deposit", Some(dec!(1)), None),
+            Ok(TransactionType::Deposit(dec!(1)))
+        );
+        assert_eq!(
+            TransactionType::parse("WITHDRAWAL", Some(dec!(1)), None),
+            Ok(TransactionType::Withdraw(dec!(1)))
+        );
+        assert_eq!(
+            TransactionType::parse("ChargeBack", None, None),
+            Ok(TransactionType::ChargeBack)
+        );
+    }
+
+    #[test]
+    fn test_parse_withdraw_alias() {
+        assert_eq!(
+            TransactionType::parse("withdraw", Some(dec!(2.5)), None),
+            Ok(TransactionType::Withdraw(dec!(2.5)))
+        );
+        assert_eq!(
+            TransactionType::parse("Withdraw", None, None),
+            Err(TransactionError::MissingAmount)
+        );
+    }
+
+    #[test]
+    fn test_create_from_kind() {
+        assert_eq!(
+            Transaction::create("withdrawal", 3, 12, Some(dec!(1.5)), None),
+            Transaction::create_withdraw(3, 12, dec!(1.5))
+        );
+        assert_eq!(
+            Transaction::create("dispute", 3, 12, None, None),
+            Transaction::create_dispute(3, 12)
+        );
+        assert_eq!(
+            Transaction::create("refund", 3, 12, None, None),
+            Err(TransactionError::UnknownTransactionType(String::from(
+                "refund"
+            )))
+        );
+        assert_eq!(
+            Transaction::create("dispute", 3, 12, Some(dec!(0.5)), None),
+            Transaction::create_partial_dispute(3, 12, dec!(0.5))
+        );
+        assert_eq!(
+            Transaction::create("dispute", 3, 12, Some(dec!(0)), None),
+            Err(TransactionError::InvalidAmount(dec!(0)))
+        );
+        assert_eq!(
+            Transaction::create("chargeback", 3, 12, Some(dec!(1)), None),
+            Err(TransactionError::UnexpectedAmount)
+        );
+        assert_eq!(
+            Transaction::create("deposit", 3, 12, None, None),
+            Err(TransactionError::MissingAmount)
+        );
+        assert_eq!(
+            Transaction::create("deposit", 3, 12, Some(dec!(-1)), None),
+            Err(TransactionError::InvalidAmount(dec!(-1)))
+        );
+    }
+
+    #[test]
+    fn test_create_transfer() {
+        assert_eq!(
+            Transaction::create("Transfer", 3, 12, Some(dec!(1.5)), Some(4)),
+            Ok(Transaction {
+                client: 3,
+                tx: 12,
+                kind: TransactionType::Transfer {
+                    to: 4,
+                    amount: dec!(1.5)
+                },
+                currency: None,
+            })
+        );
+        assert_eq!(
+            Transaction::create("transfer", 3, 12, Some(dec!(1.5)), None),
+            Err(TransactionError::MissingDestination)
+        );
+        assert_eq!(
+            Transaction::create("transfer", 3, 12, None, Some(4)),
+            Err(TransactionError::MissingAmount)
+        );
+        assert_eq!(
+            Transaction::create("deposit", 3, 12, Some(dec!(1.5)), Some(4)),
+            Err(TransactionError::UnexpectedDestination)
+        );
+        assert!(Transaction::create_transfer(3, 12, 3, dec!(1.5)).is_err());
+        assert_eq!(
+            Transaction::create_transfer(3, 12, 4, dec!(-1)),
+            Err(TransactionError::InvalidAmount(dec!(-1)))
+        );
+    }
+
+    #[test]
+    fn test_amount() {
+        let amount = dec!(1.5);
+        assert_eq!(
+            Transaction::create_deposit(3, 12, amount).unwrap().amount(),
+            Some(amount)
+        );
+        assert_eq!(
+            Transaction::create_withdraw(3, 12, amount)
+                .unwrap()
+                .amount(),
+            Some(amount)
+        );
+        assert_eq!(
+            Transaction::create_transfer(3, 12, 4, amount)
+                .unwrap()
+                .amount(),
+            Some(amount)
+        );
+        assert_eq!(Transaction::create_dispute(3, 12).unwrap().amount(), None);
+        assert_eq!(
+            Transaction::create_partial_dispute(3, 12, amount)
+                .unwrap()
+                .amount(),
+            None
+        );
+        assert_eq!(Transaction::create_resolve(3, 12).unwrap().amount(), None);
+        assert_eq!(
+            Transaction::create_chargeback(3, 12).unwrap().amount(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_same_id() {
+        let deposit = Transaction::create_deposit(3, 12, dec!(15.33)).unwrap();
+        let dispute = Transaction::create_dispute(3, 12).unwrap();
+        let other_client = Transaction::create_dispute(4, 12).unwrap();
+
+        assert!(deposit.same_id(&dispute));
+        assert_ne!(deposit, dispute);
+        assert!(!dispute.same_id(&other_client));
+    }
 }
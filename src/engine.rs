@@ -0,0 +1,261 @@
+use log::warn;
+use std::io;
+
+use crate::domain::transaction::{ClientID, TransactionID};
+use crate::domain::{
+    AccountReport, Audit, Policy, Portfolio, ProcessingStats, Rejection, Snapshot, Transaction,
+};
+use crate::error::TransactionError;
+use crate::RunResult;
+
+/// Applies transactions one at a time, for callers that receive them from
+/// somewhere other than a file, and exposes the state between them
+///
+/// ```
+/// use transactions_handler::{Transaction, TransactionEngine};
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = TransactionEngine::new();
+/// engine.apply(Transaction::create_deposit(1, 1, dec!(5)).unwrap()).unwrap();
+/// assert!(engine.apply(Transaction::create_withdraw(1, 2, dec!(6)).unwrap()).is_err());
+///
+/// let snapshots: Vec<_> = engine.snapshots().collect();
+/// assert_eq!(snapshots[0].total, dec!(5));
+/// ```
+#[derive(Debug)]
+pub struct TransactionEngine {
+    portfolio: Portfolio,
+}
+
+impl TransactionEngine {
+    pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    pub fn with_policy(policy: Policy) -> Self {
+        Self {
+            portfolio: Portfolio::with_policy(policy),
+        }
+    }
+
+    /// Applies `t` to the accounts. A refused transaction changes nothing and
+    /// is counted in [`stats`](Self::stats) and [`rejections`](Self::rejections)
+    pub fn apply(&mut self, t: Transaction) -> Result<(), TransactionError> {
+        let (client, tx) = (t.client, t.tx);
+        self.portfolio
+            .add_transaction(t)
+            .inspect_err(|err| warn!("Transaction {} of client {} rejected: {}", tx, client, err))
+    }
+
+    /// Snapshot the account of `t` would have after applying it, or the error
+    /// that would refuse it, without changing anything
+    ///
+    /// ```
+    /// use transactions_handler::{Transaction, TransactionEngine};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = TransactionEngine::new();
+    /// engine.apply(Transaction::create_deposit(1, 1, dec!(5)).unwrap()).unwrap();
+    /// let withdrawal = Transaction::create_withdraw(1, 2, dec!(2)).unwrap();
+    /// assert_eq!(engine.dry_run(&withdrawal).unwrap().total, dec!(3));
+    /// assert_eq!(engine.snapshots().next().unwrap().total, dec!(5));
+    /// ```
+    pub fn dry_run(&self, t: &Transaction) -> Result<Snapshot, TransactionError> {
+        self.portfolio.dry_run(t)
+    }
+
+    /// Folds the accounts of `other` into this engine, e.g. the partial result
+    /// of another shard or input file. Fails without changing anything when an
+    /// account or tx id is in both
+    pub fn merge(&mut self, other: TransactionEngine) -> Result<(), TransactionError> {
+        self.portfolio.merge(other.portfolio)
+    }
+
+    /// Writes the whole state as JSON, to carry on later with
+    /// [`load_state`](Self::load_state)
+    pub fn save_state<W: io::Write>(&self, writer: W) -> Result<(), TransactionError> {
+        self.portfolio.save_state(writer)
+    }
+
+    /// Restores an engine written by [`save_state`](Self::save_state). Applying
+    /// the remaining transactions gives the same result as an uninterrupted run
+    pub fn load_state<R: io::Read>(reader: R) -> Result<Self, TransactionError> {
+        Portfolio::load_state(reader).map(Self::from)
+    }
+
+    /// Resolves every open dispute, releasing the held funds, and drops the
+    /// disputes still waiting for their transaction. Nothing is charged back
+    pub fn clear_all_disputes(&mut self) -> Result<(), TransactionError> {
+        self.portfolio.clear_all_disputes()
+    }
+
+    /// Current balances in the order the clients first appeared
+    pub fn snapshots(&self) -> impl Iterator<Item = Snapshot> + '_ {
+        self.portfolio.snapshots()
+    }
+
+    /// Current balances of `client` in the implicit currency
+    pub fn get_account(&self, client: ClientID) -> Option<Snapshot> {
+        self.portfolio.get_account(client)
+    }
+
+    /// Detailed state of the account of `client` in the implicit currency,
+    /// including how much of `held` comes from disputed deposits and from
+    /// disputed withdrawals
+    pub fn account_report(&self, client: ClientID) -> Option<AccountReport> {
+        self.portfolio.get_account_report(client)
+    }
+
+    /// Ids of the clients with an account, sorted
+    pub fn client_ids(&self) -> Vec<ClientID> {
+        self.portfolio.client_ids()
+    }
+
+    /// Counters so far. Disputes waiting for their transaction are counted as
+    /// `pending` rather than applied
+    pub fn stats(&self) -> ProcessingStats {
+        self.portfolio.stats()
+    }
+
+    pub fn rejections(&self) -> &[Rejection] {
+        self.portfolio.rejections()
+    }
+
+    /// Checks that the accounts add up to the money deposited and seeded,
+    /// less the money withdrawn, taking chargebacks into account. A discrepancy
+    /// is logged as a warning and reported in the returned [`Audit`]
+    pub fn audit(&self) -> Result<Audit, TransactionError> {
+        self.portfolio.audit()
+    }
+
+    /// Everything applied so far as a [`RunResult`], with the snapshots sorted
+    /// by client id and then currency
+    pub fn result(&self) -> RunResult {
+        let mut snapshots: Vec<Snapshot> = self.snapshots().collect();
+        snapshots.sort_by_key(|s| (s.client, s.currency.clone()));
+
+        RunResult {
+            snapshots,
+            stats: self.stats(),
+            rejections: self.rejections().to_vec(),
+        }
+    }
+}
+
+// Reports the disputes that never found their transaction by the end of a run.
+pub(crate) fn warn_waiting(waiting: &[(ClientID, TransactionID)]) {
+    for (client, tx) in waiting {
+        warn!(
+            "Dispute of transaction {} of client {} is still waiting for it",
+            tx, client
+        );
+    }
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Portfolio> for TransactionEngine {
+    fn from(portfolio: Portfolio) -> Self {
+        Self { portfolio }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::capture::capture_logs;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_engine_applies_transactions() {
+        let mut engine = TransactionEngine::new();
+        for t in [
+            Transaction::create_deposit(2, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(1, 2, dec!(3)).unwrap(),
+            Transaction::create_withdraw(2, 3, dec!(4)).unwrap(),
+            Transaction::create_dispute(1, 2).unwrap(),
+        ] {
+            engine.apply(t).unwrap();
+        }
+        assert_eq!(
+            engine.apply(Transaction::create_withdraw(1, 4, dec!(1)).unwrap()),
+            Err(TransactionError::InsufficientFunds)
+        );
+        engine
+            .apply(Transaction::create_chargeback(1, 2).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            engine.snapshots().collect::<Vec<_>>(),
+            vec![
+                Snapshot {
+                    client: 2,
+                    total: dec!(6),
+                    held: dec!(0),
+                    locked: false,
+                    currency: None,
+                },
+                Snapshot {
+                    client: 1,
+                    total: dec!(0),
+                    held: dec!(0),
+                    locked: true,
+                    currency: None,
+                },
+            ]
+        );
+        assert_eq!(engine.client_ids(), vec![1, 2]);
+        assert_eq!(engine.get_account(2).unwrap().total, dec!(6));
+        assert_eq!(engine.get_account(3), None);
+        assert_eq!(engine.stats().processed, 6);
+        assert_eq!(engine.rejections().len(), 1);
+        assert_eq!(engine.result().snapshots[0].client, 1);
+    }
+
+    #[test]
+    fn test_held_breakdown() {
+        let mut engine = TransactionEngine::new();
+        for t in [
+            Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+            Transaction::create_deposit(1, 2, dec!(3)).unwrap(),
+            Transaction::create_withdraw(1, 3, dec!(4)).unwrap(),
+            Transaction::create_dispute(1, 2).unwrap(),
+            Transaction::create_dispute(1, 3).unwrap(),
+        ] {
+            engine.apply(t).unwrap();
+        }
+
+        let report = engine.account_report(1).unwrap();
+        assert_eq!(report.held_by_deposits, dec!(3));
+        assert_eq!(report.held_by_withdrawals, dec!(4));
+        assert_eq!(report.snapshot.held, dec!(7));
+        assert_eq!(report.snapshot.total, dec!(13));
+        assert_eq!(report.disputed, vec![2, 3]);
+        assert_eq!(engine.account_report(2), None);
+    }
+    #[test]
+    fn test_waiting_disputes_are_pending() {
+        let capture = capture_logs();
+        let mut engine = TransactionEngine::new();
+        for t in [
+            Transaction::create_deposit(41, 1, dec!(10)).unwrap(),
+            Transaction::create_dispute(41, 9041).unwrap(),
+            Transaction::create_dispute(42, 9042).unwrap(),
+            Transaction::create_dispute(41, 1).unwrap(),
+        ] {
+            engine.apply(t).unwrap();
+        }
+
+        let stats = engine.result().stats;
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.applied(), 2);
+        // Only the end of a run reports them
+        let messages = capture.messages.lock().unwrap();
+        let waiting = "Dispute of transaction 9041 of client 41 is still waiting for it";
+        assert!(!messages.iter().any(|(_, message)| message == waiting));
+    }
+}
@@ -0,0 +1,145 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, io};
+
+use crate::domain::transaction::{ClientID, TransactionID};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TransactionError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+    InvalidAmount(Decimal),
+    AmountAboveLimit {
+        amount: Decimal,
+        limit: Decimal,
+    },
+    MissingAmount,
+    UnexpectedAmount,
+    MissingDestination,
+    UnexpectedDestination,
+    FieldCount {
+        expected: usize,
+        found: usize,
+    },
+    UnknownTransactionType(String),
+    ClientOutOfRange {
+        line: u64,
+        value: String,
+    },
+    DuplicateTxId(TransactionID),
+    InsufficientFunds,
+    BalanceOverflow,
+    AccountLocked,
+    ClientMismatch(TransactionID),
+    CurrencyMismatch(TransactionID),
+    DuplicateClient(ClientID),
+    TooManyAccounts(usize),
+    NotDisputed(TransactionID),
+    UnknownTx(TransactionID),
+    NotDisputable(TransactionID),
+    DisputeExceedsAmount {
+        tx: TransactionID,
+        amount: Decimal,
+    },
+    AlreadyReversed(TransactionID),
+    // A record of the input that could not be turned into a transaction
+    InvalidRow {
+        line: u64,
+        client: ClientID,
+        tx: TransactionID,
+        reason: Box<TransactionError>,
+    },
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Io(msg) => write!(f, "Error reading input: {}", msg),
+            TransactionError::Parse(msg) => write!(f, "Error parsing transactions: {}", msg),
+            TransactionError::Invalid(msg) => write!(f, "Invalid transaction: {}", msg),
+            TransactionError::InvalidAmount(amount) => {
+                write!(f, "Amount must be positive, got {}", amount)
+            }
+            TransactionError::AmountAboveLimit { amount, limit } => {
+                write!(f, "Amount {} is above the limit of {}", amount, limit)
+            }
+            TransactionError::MissingAmount => write!(f, "Amount is required"),
+            TransactionError::UnexpectedAmount => {
+                write!(f, "Amount is not allowed for this transaction type")
+            }
+            TransactionError::MissingDestination => write!(f, "Destination client is required"),
+            TransactionError::UnexpectedDestination => {
+                write!(f, "Destination client is only allowed for transfers")
+            }
+            TransactionError::FieldCount { expected, found } => {
+                write!(f, "Expected {} fields, found {}", expected, found)
+            }
+            TransactionError::UnknownTransactionType(kind) => {
+                write!(f, "Unknown transaction type {}", kind)
+            }
+            TransactionError::ClientOutOfRange { line, value } => {
+                write!(f, "client id out of range at line {}: {}", line, value)
+            }
+            TransactionError::DuplicateTxId(tx) => write!(f, "Duplicate transaction id {}", tx),
+            TransactionError::InsufficientFunds => write!(f, "Insufficient funds"),
+            TransactionError::BalanceOverflow => write!(f, "Balance would overflow"),
+            TransactionError::AccountLocked => write!(f, "Account is locked"),
+            TransactionError::ClientMismatch(tx) => {
+                write!(f, "Transaction {} belongs to another client", tx)
+            }
+            TransactionError::CurrencyMismatch(tx) => {
+                write!(f, "Transaction {} is in another currency", tx)
+            }
+            TransactionError::DuplicateClient(client) => {
+                write!(f, "Client {} is in both portfolios", client)
+            }
+            TransactionError::TooManyAccounts(limit) => {
+                write!(f, "Cannot open more than {} accounts", limit)
+            }
+            TransactionError::NotDisputed(tx) => write!(f, "Transaction {} is not disputed", tx),
+            TransactionError::UnknownTx(tx) => write!(f, "Transaction {} is unknown", tx),
+            TransactionError::NotDisputable(tx) => {
+                write!(f, "Transaction {} cannot be disputed", tx)
+            }
+            TransactionError::DisputeExceedsAmount { tx, amount } => {
+                write!(
+                    f,
+                    "Dispute of {} exceeds the amount of transaction {}",
+                    amount, tx
+                )
+            }
+            TransactionError::AlreadyReversed(tx) => {
+                write!(f, "Transaction {} was already reversed", tx)
+            }
+            TransactionError::InvalidRow {
+                line,
+                client,
+                tx,
+                reason,
+            } => write!(
+                f,
+                "Invalid record at line {} (client {}, tx {}): {}",
+                line, client, tx, reason
+            ),
+        }
+    }
+}
+
+impl Error for TransactionError {}
+
+impl From<io::Error> for TransactionError {
+    fn from(err: io::Error) -> Self {
+        TransactionError::Io(err.to_string())
+    }
+}
+
+impl From<csv::Error> for TransactionError {
+    fn from(err: csv::Error) -> Self {
+        if err.is_io_error() {
+            TransactionError::Io(err.to_string())
+        } else {
+            TransactionError::Parse(err.to_string())
+        }
+    }
+}
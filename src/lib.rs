@@ -1,31 +1,295 @@
+pub mod checkpoint;
+pub mod cli;
 mod domain;
+mod engine;
+pub mod error;
+pub mod logger;
+mod parallel;
 pub mod reader;
 pub mod writer;
 
-use reader::{get_content, get_reader};
-use writer::{write, write_headers};
+pub use domain::{AccountReport, Audit, Policy, Portfolio, ProcessingStats, Rejection, Snapshot};
+pub use domain::{Overdraw, Transaction, TransactionType, WithdrawalDisputes};
+pub use engine::TransactionEngine;
+pub use parallel::run_parallel;
+
+use std::{error::Error, fs::File, io, path::Path};
+
+use checkpoint::{Checkpoint, CheckpointOptions};
+use engine::warn_waiting;
+use error::TransactionError;
+use reader::{
+    get_content_from, get_content_from_inputs, input, reader_builder, validate_input,
+    ProcessingControl, ReaderOptions,
+};
+use writer::{write_csv, write_fixed_width, write_jsonl, OutputFormat, OutputOptions};
 
 /// Application runner
 ///
-/// Receives a String representing the filename of a CSV containing
+/// Receives the path of a CSV containing
 /// a series of transactions, and processes the payments crediting and debiting accounts.
 /// After processing the complete set of payments output the client account balances
 ///
 /// ```
-/// let result = transactions_handler::run(String::from("tests/transactions.csv"));
-/// assert_eq!(result, ());
+/// let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/transactions.csv");
+/// let result = transactions_handler::run(fixture);
+/// assert!(result.is_ok());
+///
+/// let missing = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/missing.csv");
+/// let result = transactions_handler::run(missing);
+/// assert!(result.is_err());
+/// ```
+pub fn run(filename: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+    let out = io::BufWriter::new(io::stdout().lock());
+    run_with_config(&EngineConfig::default(), input(filename)?, out)?;
+    Ok(())
+}
+
+/// Every setting of a run: how the input is read, the business rules applied,
+/// when checkpoints are saved and how the balances are written. `output.path`
+/// is not used by [`run_with_config`], which writes wherever it is told to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineConfig {
+    pub reader: ReaderOptions,
+    pub policy: Policy,
+    pub output: OutputOptions,
+    pub checkpoints: CheckpointOptions,
+}
+
+/// Processes the transactions read from `input` and writes the account
+/// balances into `output`, both as configured in `config`. Returns the
+/// processing counters
+///
+/// ```
+/// use transactions_handler::{reader::ReaderOptions, writer::OutputOptions, EngineConfig};
+///
+/// let config = EngineConfig {
+///     reader: ReaderOptions {
+///         delimiter: b';',
+///         ..Default::default()
+///     },
+///     output: OutputOptions {
+///         scale: 2,
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let input = "type;client;tx;amount\ndeposit;1;1;1.005";
+/// let mut output = vec![];
+/// transactions_handler::run_with_config(&config, input.as_bytes(), &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "client,available,held,total,locked\n1,1.01,0.00,1.01,false\n"
+/// );
+/// ```
+pub fn run_with_config<R, W>(
+    config: &EngineConfig,
+    input: R,
+    output: W,
+) -> Result<ProcessingStats, Box<dyn Error>>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    let mut rdr = reader_builder(&config.reader).from_reader(input);
+    let start = Checkpoint {
+        rows: 0,
+        portfolio: Portfolio::with_policy(config.policy),
+    };
+    let portfolio = get_content_from(
+        &mut rdr,
+        start,
+        &config.reader,
+        &ProcessingControl::default(),
+        &config.checkpoints,
+    )?;
+    warn_waiting(&portfolio.waiting_disputes());
+    let result = TransactionEngine::from(portfolio).result();
+    let stats = result.stats.clone();
+    write_result_to(output, result, &config.output)?;
+    Ok(stats)
+}
+
+/// Processes the transactions of all the `filenames`, in order, as a single
+/// stream, as configured in `config`, without printing anything. Continues
+/// from `start`, whose accounts follow `config.policy` from then on, and saves
+/// checkpoints as configured. A filename of `-` reads the transactions from
+/// stdin. Snapshots are sorted by client id and then currency
+///
 /// ```
-pub fn run(filename: String) {
-    let mut rdr = get_reader(filename);
-    let mut portfolio = get_content(&mut rdr).unwrap();
+/// use transactions_handler::{checkpoint::Checkpoint, EngineConfig};
+///
+/// let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/transactions.csv");
+/// let config = EngineConfig::default();
+/// let result =
+///     transactions_handler::process_with_config(&config, [fixture], Checkpoint::default())
+///         .unwrap();
+/// assert_eq!(result.stats.processed, 9);
+/// ```
+pub fn process_with_config(
+    config: &EngineConfig,
+    filenames: impl IntoIterator<Item = impl AsRef<Path>>,
+    start: Checkpoint,
+) -> Result<RunResult, TransactionError> {
+    let inputs = filenames
+        .into_iter()
+        .map(input)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut start = start;
+    start.portfolio.set_policy(config.policy);
+    let portfolio = get_content_from_inputs(
+        inputs,
+        start,
+        &config.reader,
+        &ProcessingControl::default(),
+        &config.checkpoints,
+    )?;
+    warn_waiting(&portfolio.waiting_disputes());
+    Ok(TransactionEngine::from(portfolio).result())
+}
+
+/// Same as [`run`], writing the account balances according to [`OutputOptions`]
+#[deprecated(note = "use `process_with_config` and `write_result`")]
+pub fn run_with_options(
+    filename: impl AsRef<Path>,
+    options: OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let config = EngineConfig::default();
+    let result = process_with_config(&config, [filename], Checkpoint::default())?;
+    write_result(result, &options)
+}
+
+/// Writes the account balances of a [`RunResult`] to the file configured in
+/// [`OutputOptions`], or to stdout
+pub fn write_result(result: RunResult, options: &OutputOptions) -> Result<(), Box<dyn Error>> {
+    match &options.path {
+        Some(path) => write_result_to(io::BufWriter::new(File::create(path)?), result, options),
+        None => write_result_to(io::BufWriter::new(io::stdout().lock()), result, options),
+    }
+}
+
+/// Writes the account balances of a [`RunResult`] into `out`
+pub fn write_result_to<W: io::Write>(
+    out: W,
+    result: RunResult,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    match &options.format {
+        OutputFormat::Csv => write_csv(out, result.snapshots, options),
+        OutputFormat::JsonLines => write_jsonl(out, result.snapshots, options),
+        OutputFormat::FixedWidth(widths) => {
+            write_fixed_width(out, result.snapshots, widths, options)
+        }
+    }
+}
+
+/// Outcome of processing a file: the final account balances, counters and
+/// the transactions that were refused
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub snapshots: Vec<Snapshot>,
+    pub stats: ProcessingStats,
+    pub rejections: Vec<Rejection>,
+}
+
+/// Returns the final account balances for the transactions in `filename`
+/// without printing anything
+#[deprecated(note = "use `process_with_config`")]
+pub fn process(filename: impl AsRef<Path>) -> Result<Vec<Snapshot>, TransactionError> {
+    let config = EngineConfig::default();
+    Ok(process_with_config(&config, [filename], Checkpoint::default())?.snapshots)
+}
+
+/// Checks that every row of `filename` is a valid transaction and that no tx
+/// id is reused, without computing any balance. Returns every problem found,
+/// not just the first one
+///
+/// ```
+/// let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/transactions.csv");
+/// let stats = transactions_handler::validate(fixture).unwrap();
+/// assert_eq!(stats.processed, 9);
+///
+/// let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mixed.csv");
+/// let errors = transactions_handler::validate(fixture).unwrap_err();
+/// assert_eq!(errors.len(), 3);
+/// ```
+pub fn validate(filename: impl AsRef<Path>) -> Result<ProcessingStats, Vec<TransactionError>> {
+    validate_with_config(&EngineConfig::default(), filename)
+}
+
+/// Same as [`validate`], reading the input as configured in `config`
+pub fn validate_with_config(
+    config: &EngineConfig,
+    filename: impl AsRef<Path>,
+) -> Result<ProcessingStats, Vec<TransactionError>> {
+    let input = input(filename).map_err(|err| vec![err])?;
+    validate_input(input, &config.reader)
+}
+
+/// Processes the transactions in `filename` without printing anything.
+/// Snapshots are sorted by client id and then currency
+#[deprecated(note = "use `process_with_config`")]
+pub fn run_to_result(filename: impl AsRef<Path>) -> Result<RunResult, TransactionError> {
+    process_with_config(&EngineConfig::default(), [filename], Checkpoint::default())
+}
+
+/// Same as [`run_to_result`] for the transactions of all the `filenames`, in
+/// order, as a single stream. Continues from a [`Checkpoint`] and saves new
+/// ones as configured. A filename of `-` reads the transactions from stdin
+#[deprecated(note = "use `process_with_config`")]
+pub fn run_to_result_from(
+    filenames: impl IntoIterator<Item = impl AsRef<Path>>,
+    start: Checkpoint,
+    checkpoints: &CheckpointOptions,
+) -> Result<RunResult, TransactionError> {
+    let config = EngineConfig {
+        checkpoints: checkpoints.clone(),
+        ..Default::default()
+    };
+    process_with_config(&config, filenames, start)
+}
+
+/// Same as [`run_to_result_from`], reading the input as configured in
+/// [`ReaderOptions`]
+#[deprecated(note = "use `process_with_config`")]
+pub fn run_to_result_with(
+    filenames: impl IntoIterator<Item = impl AsRef<Path>>,
+    start: Checkpoint,
+    options: &ReaderOptions,
+    checkpoints: &CheckpointOptions,
+) -> Result<RunResult, TransactionError> {
+    let config = EngineConfig {
+        reader: options.clone(),
+        checkpoints: checkpoints.clone(),
+        ..Default::default()
+    };
+    process_with_config(&config, filenames, start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::capture::capture_logs;
+
+    #[test]
+    fn test_waiting_disputes_reported_at_end_of_run() {
+        let capture = capture_logs();
+        let input = "type,client,tx,amount\n\
+                     deposit,43,9043,1\n\
+                     dispute,43,9044\n\
+                     dispute,44,9045";
+        let stats =
+            run_with_config(&EngineConfig::default(), input.as_bytes(), io::sink()).unwrap();
+        assert_eq!(stats.pending, 2);
 
-    write_headers();
-    loop {
-        match portfolio.get_snapshot_line() {
-            Some(s) => write(s),
-            _ => {
-                break;
-            }
+        let messages = capture.messages.lock().unwrap();
+        for (client, tx) in [(43, 9044), (44, 9045)] {
+            let message = format!(
+                "Dispute of transaction {} of client {} is still waiting for it",
+                tx, client
+            );
+            let count = messages.iter().filter(|(_, m)| *m == message).count();
+            assert_eq!(count, 1);
         }
     }
 }
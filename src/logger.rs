@@ -0,0 +1,70 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+// Writes the messages of the enabled levels to stderr, as they were written
+// before logging was introduced. Levels are filtered by `log::max_level`.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// Installs the stderr logger showing messages up to `level`. Does nothing if a
+// logger is already installed.
+pub fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Test logger keeping every message, shared by the tests of all modules since
+// a logger can only be installed once per process. Tests run in parallel, so
+// they should look for messages only they can produce.
+#[cfg(test)]
+pub(crate) mod capture {
+    use std::sync::{Mutex, Once};
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    pub struct CaptureLogger {
+        pub messages: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let message = (record.level(), record.args().to_string());
+            self.messages.lock().unwrap().push(message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: CaptureLogger = CaptureLogger {
+        messages: Mutex::new(vec![]),
+    };
+
+    // Installs the capturing logger once for the whole test binary.
+    pub fn capture_logs() -> &'static CaptureLogger {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CAPTURE).unwrap();
+            log::set_max_level(LevelFilter::Info);
+        });
+        &CAPTURE
+    }
+}
@@ -1,13 +1,56 @@
 use std::{env::args, process::exit};
-use transactions_handler::reader::get_filename;
-use transactions_handler::run;
+use transactions_handler::checkpoint::{load_balances, load_checkpoint, Checkpoint};
+use transactions_handler::cli::parse_args;
+use transactions_handler::logger;
+use transactions_handler::{process_with_config, validate_with_config, write_result};
 
 fn main() {
     let arguments = args().collect::<Vec<String>>();
-    let filename = get_filename(arguments).unwrap_or_else(|err| {
-        eprintln!("Error getting filename: {}", err);
+    let parsed = parse_args(arguments).unwrap_or_else(|err| {
+        eprintln!("Error parsing arguments: {}", err);
         exit(1);
     });
+    logger::init(parsed.log_level);
 
-    run(filename)
+    if parsed.validate {
+        let mut valid = true;
+        for filename in parsed.filenames {
+            match validate_with_config(&parsed.config, &filename) {
+                Ok(stats) => eprintln!("{}: {} valid transactions", filename, stats.processed),
+                Err(errors) => {
+                    for err in errors.iter() {
+                        eprintln!("{}: {}", filename, err);
+                    }
+                    valid = false;
+                }
+            }
+        }
+        if !valid {
+            exit(1);
+        }
+        return;
+    }
+
+    let start = match (parsed.resume, parsed.balances) {
+        (Some(path), _) => load_checkpoint(&path).unwrap_or_else(|err| {
+            eprintln!("Error loading checkpoint: {}", err);
+            exit(1);
+        }),
+        (None, Some(path)) => load_balances(&path).unwrap_or_else(|err| {
+            eprintln!("Error loading balances: {}", err);
+            exit(1);
+        }),
+        (None, None) => Checkpoint::default(),
+    };
+
+    let result =
+        process_with_config(&parsed.config, &parsed.filenames, start).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        });
+    eprintln!("{}", result.stats);
+    write_result(result, &parsed.config.output).unwrap_or_else(|err| {
+        eprintln!("Error writing output: {}", err);
+        exit(1);
+    });
 }
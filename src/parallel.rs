@@ -0,0 +1,278 @@
+use log::warn;
+use std::{path::Path, thread};
+
+use crate::domain::transaction::{ClientID, TransactionID};
+use crate::domain::{
+    Policy, Portfolio, ProcessingStats, Rejection, Snapshot, Transaction, TransactionType, TxOwners,
+};
+use crate::engine::warn_waiting;
+use crate::error::TransactionError;
+use crate::reader::{input, read_transactions, ReaderOptions};
+use crate::RunResult;
+
+// Position of a transaction in the input, used to report rejections in the
+// same order as a serial run.
+type Position = usize;
+
+/// Same as [`process_with_config`](crate::process_with_config) with the
+/// default configuration for a single file, applying the transactions of
+/// different clients on up to `workers` threads.
+///
+/// Transaction ids are checked against the whole input before sharding, so
+/// the result is the same as a serial run.
+pub fn run_parallel(
+    filename: impl AsRef<Path>,
+    workers: usize,
+) -> Result<RunResult, TransactionError> {
+    let (transactions, skipped) = read_transactions(input(filename)?, &ReaderOptions::default())?;
+    let mut result = process_parallel(transactions, Policy::default(), workers);
+    result.stats.skipped = skipped;
+    Ok(result)
+}
+
+// Transaction ids are global, so they are claimed in input order first. The
+// remaining transactions only touch their own client's account and are split
+// by client, keeping their order within each shard. Transfers touch two
+// accounts, so an input containing any is applied on a single shard.
+fn process_parallel(transactions: Vec<Transaction>, policy: Policy, workers: usize) -> RunResult {
+    let has_transfers = transactions
+        .iter()
+        .any(|t| matches!(t.kind, TransactionType::Transfer { .. }));
+    let workers = if has_transfers { 1 } else { workers.max(1) };
+    let processed = transactions.len();
+
+    let mut owners = TxOwners::default();
+    let mut rejections = vec![];
+    let mut shards: Vec<Vec<(Position, Transaction)>> = (0..workers).map(|_| vec![]).collect();
+    for (position, t) in transactions.into_iter().enumerate() {
+        match owners.claim(&t) {
+            Ok(()) => shards[t.client as usize % workers].push((position, t)),
+            Err(reason) => rejections.push((
+                position,
+                Rejection {
+                    client: t.client,
+                    tx: t.tx,
+                    reason,
+                },
+            )),
+        }
+    }
+
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .map(|shard| scope.spawn(move || process_shard(shard, policy)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut snapshots = vec![];
+    let mut waiting = vec![];
+    for (shard_snapshots, shard_rejections, shard_waiting) in results {
+        snapshots.extend(shard_snapshots);
+        rejections.extend(shard_rejections);
+        waiting.extend(shard_waiting);
+    }
+    snapshots.sort_by_key(|s| (s.client, s.currency.clone()));
+    rejections.sort_by_key(|(position, _)| *position);
+    waiting.sort();
+
+    for (_, r) in rejections.iter() {
+        warn!(
+            "Transaction {} of client {} rejected: {}",
+            r.tx, r.client, r.reason
+        );
+    }
+    warn_waiting(&waiting);
+    RunResult {
+        snapshots,
+        stats: ProcessingStats {
+            processed,
+            rejected: rejections.len(),
+            skipped: 0,
+            pending: waiting.len(),
+        },
+        rejections: rejections.into_iter().map(|(_, r)| r).collect(),
+    }
+}
+
+type ShardResult = (
+    Vec<Snapshot>,
+    Vec<(Position, Rejection)>,
+    Vec<(ClientID, TransactionID)>,
+);
+
+fn process_shard(shard: Vec<(Position, Transaction)>, policy: Policy) -> ShardResult {
+    let mut portfolio = Portfolio::with_policy(policy);
+    let mut rejections = vec![];
+    // A deposit or withdrawal can also refuse the dispute that was waiting for
+    // it, which is reported at the position of the deposit, as in a serial run
+    for (position, t) in shard {
+        let seen = portfolio.rejections().len();
+        portfolio.add_transaction(t).ok();
+        let refused = portfolio.rejections()[seen..].iter().cloned();
+        rejections.extend(refused.map(|r| (position, r)));
+    }
+    (
+        portfolio.snapshots().collect(),
+        rejections,
+        portfolio.waiting_disputes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    // Interleaves many clients with disputes, chargebacks, overdrafts and tx
+    // ids reused across clients.
+    fn transactions() -> Vec<Transaction> {
+        let mut transactions = vec![];
+        for tx in 1..=2000u32 {
+            let client = tx * 7 % 23;
+            let t = match tx % 6 {
+                0 => Transaction::create_withdraw(client, tx, Decimal::from(tx % 50)),
+                1 => Transaction::create_dispute(client, tx - 1),
+                2 => Transaction::create_chargeback(client, tx - 2),
+                3 => Transaction::create_deposit(client + 1, tx - 3, Decimal::from(tx % 13)),
+                _ => Transaction::create_deposit(client, tx, Decimal::from(tx % 40)),
+            };
+            transactions.push(t.unwrap());
+        }
+        transactions
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let mut serial = Portfolio::new();
+        for t in transactions() {
+            serial.add_transaction(t).ok();
+        }
+        let mut snapshots: Vec<Snapshot> = serial.snapshots().collect();
+        snapshots.sort_by_key(|s| (s.client, s.currency.clone()));
+        let expected = RunResult {
+            snapshots,
+            stats: serial.stats(),
+            rejections: serial.rejections().to_vec(),
+        };
+        assert!(expected.stats.rejected > 0);
+
+        for workers in [1, 4, 16] {
+            assert_eq!(
+                process_parallel(transactions(), Policy::default(), workers),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_parallel_with_transfers() {
+        let transactions = vec![
+            Transaction::create_deposit(1, 1, Decimal::from(5)).unwrap(),
+            Transaction::create_transfer(1, 2, 2, Decimal::from(3)).unwrap(),
+            Transaction::create_withdraw(2, 3, Decimal::from(2)).unwrap(),
+        ];
+
+        let result = process_parallel(transactions, Policy::default(), 4);
+        assert_eq!(result.stats.rejected, 0);
+        assert_eq!(result.snapshots[0].total, Decimal::from(2));
+        assert_eq!(result.snapshots[1].total, Decimal::from(1));
+    }
+
+    // The refused transfer claims its id in both runs, so the deposit
+    // reusing it is rejected
+    #[test]
+    fn test_parallel_refused_transfer_claims_id() {
+        let transactions = || {
+            vec![
+                Transaction::create_deposit(1, 1, Decimal::from(5)).unwrap(),
+                Transaction::create_deposit(2, 2, Decimal::from(3)).unwrap(),
+                Transaction::create_dispute(2, 2).unwrap(),
+                Transaction::create_chargeback(2, 2).unwrap(),
+                Transaction::create_transfer(1, 3, 2, Decimal::from(1)).unwrap(),
+                Transaction::create_deposit(1, 3, Decimal::from(1)).unwrap(),
+            ]
+        };
+        let mut serial = Portfolio::new();
+        for t in transactions() {
+            serial.add_transaction(t).ok();
+        }
+
+        let result = process_parallel(transactions(), Policy::default(), 4);
+        assert_eq!(result.rejections, serial.rejections());
+        assert_eq!(
+            result.rejections[1].reason,
+            TransactionError::DuplicateTxId(3)
+        );
+        assert_eq!(result.snapshots, serial.snapshots().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parallel_pending_dispute_refused() {
+        let policy = Policy {
+            withdrawal_disputes: crate::WithdrawalDisputes::HoldOnly,
+            ..Default::default()
+        };
+        let transactions = || {
+            vec![
+                Transaction::create_dispute(1, 3).unwrap(),
+                Transaction::create_deposit(1, 1, Decimal::from(5)).unwrap(),
+                Transaction::create_deposit(2, 2, Decimal::from(1)).unwrap(),
+                Transaction::create_withdraw(1, 3, Decimal::from(5)).unwrap(),
+                Transaction::create_withdraw(2, 4, Decimal::from(9)).unwrap(),
+            ]
+        };
+        let mut serial = Portfolio::with_policy(policy);
+        for t in transactions() {
+            serial.add_transaction(t).ok();
+        }
+
+        let result = process_parallel(transactions(), policy, 4);
+        assert_eq!(result.rejections, serial.rejections());
+        assert_eq!(result.stats, serial.stats());
+        assert_eq!(result.rejections[0].tx, 3);
+    }
+
+    #[test]
+    fn test_parallel_max_accounts_claims_id() {
+        let policy = Policy {
+            max_accounts: Some(1),
+            ..Default::default()
+        };
+        let transactions = || {
+            vec![
+                Transaction::create_deposit(1, 1, Decimal::from(5)).unwrap(),
+                Transaction::create_deposit(2, 2, Decimal::from(3)).unwrap(),
+                Transaction::create_deposit(1, 2, Decimal::from(1)).unwrap(),
+            ]
+        };
+        let mut serial = Portfolio::with_policy(policy);
+        for t in transactions() {
+            serial.add_transaction(t).ok();
+        }
+
+        let result = process_parallel(transactions(), policy, 1);
+        assert_eq!(result.rejections, serial.rejections());
+        assert_eq!(
+            result.rejections[1].reason,
+            TransactionError::DuplicateTxId(2)
+        );
+    }
+
+    #[test]
+    fn test_run_parallel_file() {
+        let config = crate::EngineConfig::default();
+        let serial = crate::process_with_config(
+            &config,
+            ["tests/transactions.csv"],
+            crate::checkpoint::Checkpoint::default(),
+        )
+        .unwrap();
+        let parallel = run_parallel("tests/transactions.csv", 4).unwrap();
+        assert_eq!(parallel, serial);
+    }
+}
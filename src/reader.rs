@@ -1,80 +1,590 @@
 use csv::Reader;
-use csv::{ReaderBuilder, Trim};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use flate2::read::MultiGzDecoder;
+use log::warn;
 use rust_decimal::Decimal;
 use serde::Deserialize;
-use std::{fs::File, io};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{fs::File, io, path::Path, str::FromStr, thread, time::Duration};
 
-use crate::domain::{Portfolio, Transaction};
+use crate::checkpoint::{save_checkpoint, Checkpoint, CheckpointOptions};
+use crate::domain::transaction::{ClientID, Currency};
+use crate::domain::{
+    Policy, Portfolio, ProcessingStats, Snapshot, Transaction, TransactionKind, TxOwners,
+};
+use crate::error::TransactionError;
+
+// Filename that reads the transactions from stdin
+pub const STDIN: &str = "-";
+
+// Columns of an input without a header row
+const STANDARD_COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "to"];
 
 #[derive(Debug, Deserialize)]
 struct TransactionRow {
     r#type: String,
-    client: u16,
+    client: ClientID,
     tx: u32,
-    amount: Option<Decimal>,
+    amount: Option<String>,
+    // Destination client, only present on transfers
+    #[serde(default)]
+    to: Option<ClientID>,
+    // Only read from inputs with a header row naming a `currency` column
+    #[serde(default)]
+    currency: Option<Currency>,
+}
+
+// A row of a balances report as written by `write_csv`
+#[derive(Debug, Deserialize)]
+struct BalanceRow {
+    client: ClientID,
+    #[serde(default)]
+    currency: Option<Currency>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReaderOptions {
+    pub delimiter: u8,
+    // Amounts use a comma as decimal separator (`10,50`). Requires a delimiter
+    // other than `,`.
+    pub decimal_comma: bool,
+    // Fail on the first malformed row instead of logging and skipping it.
+    pub strict: bool,
+    // Without a header row the columns are read as `type, client, tx, amount, to`.
+    pub has_headers: bool,
+    // Drop deposits and withdrawals of zero as noise, counting them as skipped.
+    // Their tx ids are not registered, so a later dispute of one refers to an
+    // unknown transaction and never opens.
+    pub skip_zero_amounts: bool,
+    // Refuse rows with an amount above this limit, which most likely come from
+    // a corrupt feed. An amount equal to the limit is accepted.
+    pub max_amount: Option<Decimal>,
+    // Warn about amounts with more decimal places than this, usually the
+    // output scale, since they will be rounded when written. Trailing zeros
+    // do not count.
+    pub warn_above_scale: Option<u32>,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            decimal_comma: false,
+            strict: false,
+            has_headers: true,
+            skip_zero_amounts: false,
+            max_amount: None,
+            warn_above_scale: None,
+        }
+    }
+}
+
+// External flags checked between records. While `pause` is set processing
+// waits without consuming input; setting `stop` ends processing cleanly with
+// the transactions applied so far.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessingControl {
+    pub pause: Arc<AtomicBool>,
+    pub stop: Arc<AtomicBool>,
+}
+
+impl ProcessingControl {
+    // Blocks while paused. Returns false once processing should stop.
+    fn wait_while_paused(&self) -> bool {
+        while self.pause.load(Ordering::SeqCst) && !self.stop.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        !self.stop.load(Ordering::SeqCst)
+    }
+}
+
+pub fn open_reader(filename: impl AsRef<Path>) -> Result<Reader<File>, TransactionError> {
+    open_reader_with_options(filename, &ReaderOptions::default())
+}
+
+pub fn open_reader_with_options(
+    filename: impl AsRef<Path>,
+    options: &ReaderOptions,
+) -> Result<Reader<File>, TransactionError> {
+    Ok(reader_builder(options).from_path(filename)?)
+}
+
+// Opens `filename` for reading, or stdin when it is `-`. Files ending in `.gz`
+// are decompressed while they are read.
+pub fn open_input(
+    filename: impl AsRef<Path>,
+    options: &ReaderOptions,
+) -> Result<Reader<Box<dyn io::Read>>, TransactionError> {
+    Ok(reader_builder(options).from_reader(input(filename)?))
 }
 
-pub fn get_reader(filename: String) -> Reader<File> {
-    ReaderBuilder::new()
+pub(crate) fn input(filename: impl AsRef<Path>) -> Result<Box<dyn io::Read>, TransactionError> {
+    select_input(filename.as_ref(), Box::new(io::stdin()))
+}
+
+fn select_input(
+    filename: &Path,
+    stdin: Box<dyn io::Read>,
+) -> Result<Box<dyn io::Read>, TransactionError> {
+    if filename == Path::new(STDIN) {
+        return Ok(stdin);
+    }
+    let file = File::open(filename)?;
+    if filename.extension().is_some_and(|ext| ext == "gz") {
+        return Ok(Box::new(MultiGzDecoder::new(io::BufReader::new(file))));
+    }
+    Ok(Box::new(file))
+}
+
+pub fn reader_builder(options: &ReaderOptions) -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder
         .flexible(true)
         .trim(Trim::All)
-        .from_path(filename)
-        .unwrap()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers);
+    builder
 }
 
-pub fn get_content<R>(rdr: &mut Reader<R>) -> Result<Portfolio, &str>
+// Parses the CSV in `input` lazily, one transaction per record. Malformed
+// records are yielded as errors and iteration can go on past them.
+pub fn transactions<R>(input: R) -> impl Iterator<Item = Result<Transaction, TransactionError>>
 where
     R: io::Read,
 {
-    let mut portfolio = Portfolio::new();
-    for result in rdr.deserialize() {
-        let record: TransactionRow = match result {
-            Ok(transaction) => transaction,
-            _ => return Err("Error parsing transactions"),
+    transactions_with(input, &ReaderOptions::default())
+}
+
+// Same as `transactions`, reading the CSV as configured by `options`. Only the
+// format options apply; skipping and strictness are left to the caller.
+pub fn transactions_with<R>(
+    input: R,
+    options: &ReaderOptions,
+) -> impl Iterator<Item = Result<Transaction, TransactionError>>
+where
+    R: io::Read,
+{
+    let options = options.clone();
+    let mut rdr = reader_builder(&options).from_reader(input);
+    let headers = read_headers(&mut rdr, &options);
+    let failed = headers.as_ref().err().cloned();
+    let records = headers.ok().map(|headers| {
+        rdr.into_records()
+            .map(move |record| parse_line(&record?, &headers, &options))
+    });
+    failed
+        .into_iter()
+        .map(Err)
+        .chain(records.into_iter().flatten())
+}
+
+pub fn get_content<R>(rdr: &mut Reader<R>) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    get_content_with(rdr, Policy::default(), &ReaderOptions::default())
+}
+
+pub fn get_content_with<R>(
+    rdr: &mut Reader<R>,
+    policy: Policy,
+    options: &ReaderOptions,
+) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    get_content_controlled(rdr, policy, options, &ProcessingControl::default())
+}
+
+pub fn get_content_controlled<R>(
+    rdr: &mut Reader<R>,
+    policy: Policy,
+    options: &ReaderOptions,
+    control: &ProcessingControl,
+) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    let start = Checkpoint {
+        rows: 0,
+        portfolio: Portfolio::with_policy(policy),
+    };
+    get_content_from(rdr, start, options, control, &CheckpointOptions::default())
+}
+
+// Processes records after the `start.rows` already applied to `start.portfolio`,
+// saving a checkpoint as configured by `checkpoints`.
+pub fn get_content_from<R>(
+    rdr: &mut Reader<R>,
+    start: Checkpoint,
+    options: &ReaderOptions,
+    control: &ProcessingControl,
+    checkpoints: &CheckpointOptions,
+) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    let headers = read_headers(rdr, options)?;
+    let parsed = rdr
+        .records()
+        .map(|record| parse_line(&record?, &headers, options));
+    apply_parsed(parsed, start, options, control, checkpoints)
+}
+
+// Same as `get_content_from`, reading the inputs one after the other as a
+// single stream of records. Each input has its own header row, and
+// `start.rows` counts the records of all of them.
+pub fn get_content_from_inputs<R>(
+    inputs: Vec<R>,
+    start: Checkpoint,
+    options: &ReaderOptions,
+    control: &ProcessingControl,
+    checkpoints: &CheckpointOptions,
+) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    check_options(options)?;
+    let parsed = inputs
+        .into_iter()
+        .flat_map(|input| transactions_with(input, options));
+    apply_parsed(parsed, start, options, control, checkpoints)
+}
+
+fn apply_parsed<I>(
+    parsed: I,
+    start: Checkpoint,
+    options: &ReaderOptions,
+    control: &ProcessingControl,
+    checkpoints: &CheckpointOptions,
+) -> Result<Portfolio, TransactionError>
+where
+    I: Iterator<Item = Result<Transaction, TransactionError>>,
+{
+    let Checkpoint {
+        mut rows,
+        mut portfolio,
+    } = start;
+    let mut parsed = parsed.skip(rows);
+    while control.wait_while_paused() {
+        let parsed = match parsed.next() {
+            Some(parsed) => parsed,
+            None => break,
         };
+        rows += 1;
 
-        match record.r#type.as_str() {
-            "deposit" => {
-                let t =
-                    Transaction::create_deposit(record.client, record.tx, record.amount.unwrap())?;
-                portfolio.add_transaction(t).unwrap();
+        match parsed {
+            Ok(t) if options.skip_zero_amounts && is_zero_amount(&t) => portfolio.skip_record(),
+            Ok(t) => {
+                let (client, tx) = (t.client, t.tx);
+                if let Err(err) = portfolio.add_transaction(t) {
+                    warn!("Transaction {} of client {} rejected: {}", tx, client, err);
+                }
             }
-            "withdrawal" => {
-                let t =
-                    Transaction::create_withdraw(record.client, record.tx, record.amount.unwrap())?;
-                portfolio.add_transaction(t).unwrap();
+            Err(err) if options.strict => return Err(err),
+            Err(err) => {
+                warn!("Skipping: {}", err);
+                portfolio.skip_record();
             }
-            "dispute" => {
-                let t = Transaction::create_dispute(record.client, record.tx)?;
-                portfolio.add_transaction(t).unwrap();
+        }
+
+        if let Some(every) = checkpoints.every {
+            if every > 0 && rows % every == 0 {
+                let checkpoint = Checkpoint { rows, portfolio };
+                save_checkpoint(&checkpoint, &checkpoints.path)?;
+                portfolio = checkpoint.portfolio;
             }
+        }
+    }
+    Ok(portfolio)
+}
+
+// Builds a portfolio from a balances report, such as the output of an earlier
+// run, to apply new transactions on top of it. Each row must add up, i.e.
+// `available + held == total`.
+pub fn read_balances<R>(input: R) -> Result<Portfolio, TransactionError>
+where
+    R: io::Read,
+{
+    let mut rdr = reader_builder(&ReaderOptions::default()).from_reader(input);
+    let mut portfolio = Portfolio::new();
+    for row in rdr.deserialize() {
+        let row: BalanceRow = row?;
+        if row.available + row.held != row.total {
+            return Err(TransactionError::Invalid(format!(
+                "Balances of client {} do not add up",
+                row.client
+            )));
+        }
+        portfolio.seed(Snapshot {
+            client: row.client,
+            total: row.total,
+            held: row.held,
+            locked: row.locked,
+            currency: row.currency,
+        })?;
+    }
+    Ok(portfolio)
+}
 
-            "chargeback" => {
-                let t = Transaction::create_chargeback(record.client, record.tx)?;
-                portfolio.add_transaction(t).unwrap();
+// Parses every record without applying it, skipping or failing on malformed
+// rows like `get_content_with` does. Returns the transactions and the number
+// of skipped rows.
+pub(crate) fn read_transactions<R>(
+    input: R,
+    options: &ReaderOptions,
+) -> Result<(Vec<Transaction>, usize), TransactionError>
+where
+    R: io::Read,
+{
+    check_options(options)?;
+    let mut transactions = vec![];
+    let mut skipped = 0;
+    for parsed in transactions_with(input, options) {
+        match parsed {
+            Ok(t) if options.skip_zero_amounts && is_zero_amount(&t) => skipped += 1,
+            Ok(t) => transactions.push(t),
+            Err(err) if options.strict => return Err(err),
+            Err(err) => {
+                warn!("Skipping: {}", err);
+                skipped += 1;
             }
+        }
+    }
+    Ok((transactions, skipped))
+}
 
-            "resolve" => {
-                let t = Transaction::create_resolve(record.client, record.tx)?;
-                portfolio.add_transaction(t).unwrap();
+// Checks every record of `input` without applying any: each row must parse
+// into a transaction and new transactions must not reuse a tx id. Returns all
+// the problems found, each with the line of its row, or the stats of a run
+// where none were found. Balances are not computed, so rejections like
+// insufficient funds are not detected.
+pub fn validate_input<R>(
+    input: R,
+    options: &ReaderOptions,
+) -> Result<ProcessingStats, Vec<TransactionError>>
+where
+    R: io::Read,
+{
+    let mut rdr = reader_builder(options).from_reader(input);
+    let headers = read_headers(&mut rdr, options).map_err(|err| vec![err])?;
+    let mut owners = TxOwners::default();
+    let mut stats = ProcessingStats::default();
+    let mut errors = vec![];
+    for record in rdr.records() {
+        let checked = record.map_err(TransactionError::from).and_then(|record| {
+            let t = parse_line(&record, &headers, options)?;
+            // A skipped row never reaches the portfolio, so its id stays free
+            if options.skip_zero_amounts && is_zero_amount(&t) {
+                return Ok(None);
             }
-            _ => {}
+            owners
+                .claim(&t)
+                .map_err(|reason| TransactionError::InvalidRow {
+                    line: record.position().map_or(0, |p| p.line()),
+                    client: t.client,
+                    tx: t.tx,
+                    reason: Box::new(reason),
+                })?;
+            Ok(Some(t))
+        });
+        match checked {
+            Ok(None) => stats.skipped += 1,
+            Ok(Some(_)) => stats.processed += 1,
+            Err(err) => errors.push(err),
         }
     }
-    Ok(portfolio)
+
+    if errors.is_empty() {
+        Ok(stats)
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_zero_amount(t: &Transaction) -> bool {
+    t.amount().is_some_and(|amount| amount.is_zero())
+}
+
+fn check_options(options: &ReaderOptions) -> Result<(), TransactionError> {
+    if options.decimal_comma && options.delimiter == b',' {
+        return Err(TransactionError::Parse(String::from(
+            "Decimal comma requires a delimiter other than ','",
+        )));
+    }
+    Ok(())
+}
+
+fn read_headers<R>(
+    rdr: &mut Reader<R>,
+    options: &ReaderOptions,
+) -> Result<StringRecord, TransactionError>
+where
+    R: io::Read,
+{
+    check_options(options)?;
+    if !options.has_headers {
+        return Ok(StringRecord::from(STANDARD_COLUMNS.to_vec()));
+    }
+    Ok(rdr.headers()?.clone())
+}
+
+// Parses a raw record, attaching its line, client and tx to any error so the
+// offending row can be found in the input.
+fn parse_line(
+    record: &StringRecord,
+    headers: &StringRecord,
+    options: &ReaderOptions,
+) -> Result<Transaction, TransactionError> {
+    let line = record.position().map_or(0, |p| p.line());
+    let row: TransactionRow = record
+        .deserialize(Some(headers))
+        .map_err(|_| malformed_record(record, headers, line))?;
+
+    let (client, tx) = (row.client, row.tx);
+    check_field_count(&row.r#type, record, headers)
+        .and_then(|()| parse_record(row, options))
+        .map_err(|reason| TransactionError::InvalidRow {
+            line,
+            client,
+            tx,
+            reason: Box::new(reason),
+        })
+}
+
+// Explains why a record could not be deserialized. A client id that is a number
+// but does not fit a `ClientID` gets its own error, anything else is reported as
+// malformed.
+fn malformed_record(record: &StringRecord, headers: &StringRecord, line: u64) -> TransactionError {
+    let client = headers
+        .iter()
+        .position(|header| header == "client")
+        .and_then(|i| record.get(i));
+    match client {
+        Some(value) if value.parse::<i128>().is_ok() && value.parse::<ClientID>().is_err() => {
+            TransactionError::ClientOutOfRange {
+                line,
+                value: String::from(value),
+            }
+        }
+        _ => TransactionError::Parse(format!("malformed record at line {}", line)),
+    }
+}
+
+// The reader is flexible so rows only carry the fields their type needs:
+// disputes, resolves and chargebacks may stop after `tx`, deposits and
+// withdrawals need the `amount` and transfers also the `to` field. Trailing
+// empty fields are fine, but nothing may follow the known columns: the
+// standard ones, or the header row when it names more, e.g. a `currency`.
+fn check_field_count(
+    kind: &str,
+    record: &StringRecord,
+    headers: &StringRecord,
+) -> Result<(), TransactionError> {
+    let required = match TransactionKind::parse(kind)? {
+        TransactionKind::Deposit | TransactionKind::Withdraw => 4,
+        TransactionKind::Transfer => 5,
+        TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::ChargeBack => 3,
+    };
+    let filled = record
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| !field.is_empty())
+        .last()
+        .map_or(0, |(last, _)| last + 1);
+
+    if record.len() < required {
+        return Err(TransactionError::FieldCount {
+            expected: required,
+            found: record.len(),
+        });
+    }
+    if filled > STANDARD_COLUMNS.len().max(headers.len()) {
+        return Err(TransactionError::FieldCount {
+            expected: required,
+            found: filled,
+        });
+    }
+    Ok(())
+}
+
+fn parse_record(
+    record: TransactionRow,
+    options: &ReaderOptions,
+) -> Result<Transaction, TransactionError> {
+    let amount = parse_amount(record.amount.as_deref(), options.decimal_comma)?;
+    if let (Some(amount), Some(limit)) = (amount, options.max_amount) {
+        if amount > limit {
+            return Err(TransactionError::AmountAboveLimit { amount, limit });
+        }
+    }
+    if let (Some(amount), Some(scale)) = (amount, options.warn_above_scale) {
+        if amount.normalize().scale() > scale {
+            warn!(
+                "Amount {} of transaction {} has more than {} decimal places and will be rounded",
+                amount, record.tx, scale
+            );
+        }
+    }
+    let t = Transaction::create(&record.r#type, record.client, record.tx, amount, record.to)?;
+    Ok(t.in_currency(record.currency))
+}
+
+fn parse_amount(
+    raw: Option<&str>,
+    decimal_comma: bool,
+) -> Result<Option<Decimal>, TransactionError> {
+    let raw = match raw {
+        Some(raw) if !raw.is_empty() => raw,
+        _ => return Ok(None),
+    };
+
+    let parsed = if decimal_comma {
+        Decimal::from_str(&raw.replace(',', "."))
+    } else {
+        Decimal::from_str(raw)
+    };
+    parsed
+        .map(Some)
+        .map_err(|_| TransactionError::Parse(format!("invalid amount {}", raw)))
 }
 
-pub fn get_filename(arguments: Vec<String>) -> Result<String, &'static str> {
-    if arguments.len() != 2 {
+// The input files, in the order their transactions are processed.
+pub fn get_filenames(arguments: Vec<String>) -> Result<Vec<String>, &'static str> {
+    if arguments.len() < 2 {
         return Err("Wrong number of arguments");
     }
-    Ok(arguments.get(1).unwrap().to_owned())
+    Ok(arguments
+        .iter()
+        .skip(1)
+        .map(|arg| clean_filename(arg))
+        .collect())
+}
+
+// Paths coming from wrappers sometimes arrive padded or still quoted.
+fn clean_filename(arg: &str) -> String {
+    let trimmed = arg.trim();
+    let unquoted = ['"', '\''].iter().find_map(|quote| {
+        trimmed
+            .strip_prefix(*quote)
+            .and_then(|rest| rest.strip_suffix(*quote))
+    });
+    String::from(unquoted.unwrap_or(trimmed).trim())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checkpoint::load_checkpoint;
+    use crate::logger::capture::capture_logs;
+    use crate::writer::{write_csv, OutputOptions};
     use csv::ReaderBuilder;
+    use rust_decimal_macros::dec;
 
     const DATA: &str = "\
 type, client, tx, amount
@@ -88,6 +598,139 @@ dispute, 1, 3
 withdrawal, 2, 5, 3.0
 chargeback, 1, 3";
 
+    #[test]
+    fn test_transactions_iterator() {
+        let parsed: Vec<Result<Transaction, TransactionError>> =
+            transactions(DATA.as_bytes()).collect();
+
+        assert_eq!(parsed.len(), 9);
+        assert_eq!(parsed[0], Transaction::create_deposit(1, 1, dec!(1.0)));
+        assert!(parsed.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn test_large_client_id() {
+        let data = "type,client,tx,amount\ndeposit,70000,1,1.0\ndeposit,4294967296,2,1.0";
+        let mut parsed = transactions(data.as_bytes());
+
+        assert_eq!(
+            parsed.next(),
+            Some(Transaction::create_deposit(70000, 1, dec!(1.0)))
+        );
+        assert_eq!(
+            parsed.next(),
+            Some(Err(TransactionError::ClientOutOfRange {
+                line: 3,
+                value: String::from("4294967296")
+            }))
+        );
+    }
+
+    #[test]
+    fn test_client_out_of_range() {
+        let data = "type,client,tx,amount\ndeposit,-1,1,1.0\ndeposit,one,2,1.0";
+        let mut parsed = transactions(data.as_bytes());
+
+        let err = parsed.next().unwrap().unwrap_err();
+        assert_eq!(
+            err,
+            TransactionError::ClientOutOfRange {
+                line: 2,
+                value: String::from("-1")
+            }
+        );
+        assert_eq!(err.to_string(), "client id out of range at line 2: -1");
+        assert_eq!(
+            parsed.next(),
+            Some(Err(TransactionError::Parse(String::from(
+                "malformed record at line 3"
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_transactions_iterator_yields_errors() {
+        let data = "type,client,tx,amount\ndeposit,1,1,x\ndeposit,1,2,1.0";
+        let mut parsed = transactions(data.as_bytes());
+
+        assert!(matches!(
+            parsed.next(),
+            Some(Err(TransactionError::InvalidRow { line: 2, .. }))
+        ));
+        assert_eq!(
+            parsed.next(),
+            Some(Transaction::create_deposit(1, 2, dec!(1.0)))
+        );
+        assert_eq!(parsed.next(), None);
+
+        let options = ReaderOptions {
+            delimiter: b',',
+            decimal_comma: true,
+            ..Default::default()
+        };
+        let mut parsed = transactions_with(DATA.as_bytes(), &options);
+        assert!(matches!(
+            parsed.next(),
+            Some(Err(TransactionError::Parse(_)))
+        ));
+        assert_eq!(parsed.next(), None);
+    }
+
+    #[test]
+    fn test_validate_input() {
+        let stats = validate_input(DATA.as_bytes(), &ReaderOptions::default()).unwrap();
+        assert_eq!(stats.processed, 9);
+
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1.0
+depoit, 1, 2, 1.0
+withdrawal, 2, 1, 0.5
+deposit, 1, 3, -2.0
+dispute, 1, 1";
+        let errors = validate_input(data.as_bytes(), &ReaderOptions::default()).unwrap_err();
+        let reasons: Vec<(u64, TransactionError)> = errors
+            .into_iter()
+            .map(|err| match err {
+                TransactionError::InvalidRow { line, reason, .. } => (line, *reason),
+                err => panic!("unexpected error {}", err),
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (
+                    3,
+                    TransactionError::UnknownTransactionType(String::from("depoit"))
+                ),
+                (4, TransactionError::DuplicateTxId(1)),
+                (5, TransactionError::InvalidAmount(dec!(-2.0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_skipped_zero_amount_keeps_tx_id() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 0
+deposit, 1, 1, 2.0
+dispute, 1, 1";
+        let options = ReaderOptions {
+            skip_zero_amounts: true,
+            ..Default::default()
+        };
+        let stats = validate_input(data.as_bytes(), &options).unwrap();
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.skipped, 1);
+
+        // The run accepts the same rows
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+        assert!(portfolio.rejections().is_empty());
+        assert_eq!(portfolio.stats().processed, 2);
+    }
+
     #[test]
     fn test_get_content_runs() {
         let mut rdr = ReaderBuilder::new()
@@ -104,37 +747,676 @@ chargeback, 1, 3";
             .flexible(true)
             .trim(Trim::All)
             .from_reader(data.as_bytes());
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = get_content_with(&mut rdr, Policy::default(), &options).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionError::InvalidRow {
+                line: 11,
+                client: 1,
+                tx: 1,
+                reason: Box::new(TransactionError::InvalidAmount(dec!(-23))),
+            }
+        );
         assert_eq!(
-            get_content(&mut rdr).unwrap_err(),
-            "Amount must be positive"
+            err.to_string(),
+            "Invalid record at line 11 (client 1, tx 1): Amount must be positive, got -23"
         );
     }
 
+    fn strict_error(data: &str) -> TransactionError {
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        get_content_with(&mut rdr, Policy::default(), &options).unwrap_err()
+    }
+
     #[test]
-    fn test_get_filename_from_args() {
+    fn test_deposit_without_amount() {
+        let data = "type,client,tx,amount\ndeposit,1,1,";
         assert_eq!(
-            get_filename(vec![String::from("bin"), String::from("filename.csv")]).unwrap(),
-            String::from("filename.csv")
+            strict_error(data),
+            TransactionError::InvalidRow {
+                line: 2,
+                client: 1,
+                tx: 1,
+                reason: Box::new(TransactionError::MissingAmount),
+            }
         );
     }
 
     #[test]
-    fn wrong_args_number_3() {
-        match get_filename(vec![
-            String::from("bin"),
-            String::from("filename.csv"),
-            String::from("extra_arg"),
-        ]) {
-            Err(err) => {
-                assert_eq!(err, "Wrong number of arguments")
+    fn test_field_count() {
+        let data = "type,client,tx,amount\ndeposit,1,1";
+        assert_eq!(
+            strict_error(data),
+            TransactionError::InvalidRow {
+                line: 2,
+                client: 1,
+                tx: 1,
+                reason: Box::new(TransactionError::FieldCount {
+                    expected: 4,
+                    found: 3
+                }),
             }
-            _ => panic!("error expected"),
-        }
+        );
+
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0,,3";
+        assert_eq!(
+            strict_error(data),
+            TransactionError::InvalidRow {
+                line: 2,
+                client: 1,
+                tx: 1,
+                reason: Box::new(TransactionError::FieldCount {
+                    expected: 4,
+                    found: 6
+                }),
+            }
+        );
+
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0,\ndispute,1,1\nresolve,1,1,";
+        let parsed: Vec<_> = transactions(data.as_bytes()).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                Transaction::create_deposit(1, 1, dec!(2.0)),
+                Transaction::create_dispute(1, 1),
+                Transaction::create_resolve(1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dispute_with_amount() {
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,0.5";
+        let parsed: Vec<_> = transactions(data.as_bytes()).collect();
+        assert_eq!(
+            parsed[1],
+            Transaction::create_partial_dispute(1, 1, dec!(0.5))
+        );
+    }
+
+    #[test]
+    fn test_currency_column() {
+        let data = "type,client,tx,amount,to,currency\n\
+                    deposit,1,1,2.0,,USD\n\
+                    deposit,1,2,3.0,,EUR\n\
+                    deposit,1,3,1.0,,";
+        let parsed: Vec<_> = transactions(data.as_bytes()).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                Ok(Transaction::create_deposit(1, 1, dec!(2.0))
+                    .unwrap()
+                    .in_currency(Some(String::from("USD")))),
+                Ok(Transaction::create_deposit(1, 2, dec!(3.0))
+                    .unwrap()
+                    .in_currency(Some(String::from("EUR")))),
+                Transaction::create_deposit(1, 3, dec!(1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_amount() {
+        let options = ReaderOptions {
+            max_amount: Some(dec!(1000000000000)),
+            ..Default::default()
+        };
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,999999999999.9999\n\
+                    deposit,1,2,1000000000000\n\
+                    withdrawal,1,3,1000000000000.0001";
+        let parsed: Vec<_> = transactions_with(data.as_bytes(), &options).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                Transaction::create_deposit(1, 1, dec!(999999999999.9999)),
+                Transaction::create_deposit(1, 2, dec!(1000000000000)),
+                Err(TransactionError::InvalidRow {
+                    line: 4,
+                    client: 1,
+                    tx: 3,
+                    reason: Box::new(TransactionError::AmountAboveLimit {
+                        amount: dec!(1000000000000.0001),
+                        limit: dec!(1000000000000),
+                    }),
+                }),
+            ]
+        );
+
+        // Without a limit any amount is accepted
+        let parsed: Vec<_> = transactions(data.as_bytes()).collect();
+        assert!(parsed.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn test_warn_above_scale() {
+        let capture = capture_logs();
+        let data = "type,client,tx,amount\n\
+                    deposit,1,58001,0.12345\n\
+                    deposit,1,58002,0.1234\n\
+                    deposit,1,58003,0.123400";
+        let rounded = |messages: &[(log::Level, String)]| {
+            messages
+                .iter()
+                .filter(|(level, message)| {
+                    *level == log::Level::Warn && message.contains("transaction 5800")
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        // Off by default
+        transactions(data.as_bytes()).for_each(drop);
+        assert!(rounded(&capture.messages.lock().unwrap()).is_empty());
+
+        let options = ReaderOptions {
+            warn_above_scale: Some(4),
+            ..Default::default()
+        };
+        let parsed: Vec<_> = transactions_with(data.as_bytes(), &options).collect();
+        assert!(parsed.iter().all(Result::is_ok));
+        assert_eq!(
+            rounded(&capture.messages.lock().unwrap()),
+            vec![(
+                log::Level::Warn,
+                String::from(
+                    "Amount 0.12345 of transaction 58001 has more than 4 decimal places and will be rounded"
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_read_balances() {
+        let balances = "client,available,held,total,locked\n\
+                        1,1.5000,0.0000,1.5000,true\n\
+                        2,2.0000,0.0000,2.0000,false\n";
+        let mut portfolio = read_balances(balances.as_bytes()).unwrap();
+        portfolio
+            .add_transaction(Transaction::create_deposit(1, 1, dec!(3)).unwrap())
+            .ok();
+        portfolio
+            .add_transaction(Transaction::create_deposit(2, 2, dec!(3)).unwrap())
+            .unwrap();
+
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(1.5));
+        assert!(s.locked);
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(5));
+        assert_eq!(portfolio.stats().rejected, 1);
+
+        let balances = "client,available,held,total,locked\n1,1.0,0.0,2.0,false\n";
+        assert_eq!(
+            read_balances(balances.as_bytes()).unwrap_err(),
+            TransactionError::Invalid(String::from("Balances of client 1 do not add up"))
+        );
+    }
+
+    #[test]
+    fn test_balances_round_trip() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(1.5),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(7.25),
+                held: dec!(0),
+                locked: true,
+                currency: None,
+            },
+        ];
+        let mut output = vec![];
+        write_csv(&mut output, snapshots.clone(), &OutputOptions::default()).unwrap();
+
+        let portfolio = read_balances(output.as_slice()).unwrap();
+        assert_eq!(portfolio.snapshots().collect::<Vec<_>>(), snapshots);
+    }
+
+    #[test]
+    fn test_resolve_with_amount() {
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1\nresolve,1,1,2.0";
+        assert_eq!(
+            strict_error(data),
+            TransactionError::InvalidRow {
+                line: 4,
+                client: 1,
+                tx: 1,
+                reason: Box::new(TransactionError::UnexpectedAmount),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_type_is_reported() {
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0\ndepoit,1,2,3.0";
+        let mut rdr = reader_builder(&ReaderOptions::default()).from_reader(data.as_bytes());
+        let portfolio = get_content(&mut rdr).unwrap();
+        assert_eq!(portfolio.stats().processed, 1);
+        assert_eq!(portfolio.stats().skipped, 1);
+
+        assert_eq!(
+            strict_error(data),
+            TransactionError::InvalidRow {
+                line: 3,
+                client: 1,
+                tx: 2,
+                reason: Box::new(TransactionError::UnknownTransactionType(String::from(
+                    "depoit"
+                ))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_csv_error_is_transaction_error() {
+        let data = "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1";
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let mut rdr = ReaderBuilder::new().from_reader(data.as_bytes());
+        assert!(matches!(
+            get_content_with(&mut rdr, Policy::default(), &options),
+            Err(TransactionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_content_skips_malformed_rows() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1.0
+this is, not, a transaction
+deposit, 1, 2, abc
+deposit, 1, 3
+deposit, 2, 4, 2.0";
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let portfolio = get_content(&mut rdr).unwrap();
+
+        assert_eq!(portfolio.stats().processed, 2);
+        assert_eq!(portfolio.stats().skipped, 3);
+        let mut snapshots = portfolio.snapshots();
+        assert_eq!(snapshots.next().unwrap().total, dec!(1.0));
+        assert_eq!(snapshots.next().unwrap().total, dec!(2.0));
+        assert_eq!(snapshots.next(), None);
+    }
+
+    #[test]
+    fn test_get_content_decimal_comma() {
+        let data = "\
+type;client;tx;amount
+deposit;1;1;10,50
+withdrawal;1;2;0,25
+dispute;1;2";
+        let options = ReaderOptions {
+            delimiter: b';',
+            decimal_comma: true,
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+
+        let s = portfolio.snapshots().next().unwrap();
+        assert_eq!(s.total, dec!(10.50));
+        assert_eq!(s.held, dec!(0.25));
+        assert_eq!(s.get_available(), dec!(10.25));
+    }
+
+    #[test]
+    fn test_get_content_tab_delimited() {
+        let data = "type\tclient\ttx\tamount\ndeposit\t1\t1\t3.0\nwithdrawal\t1\t2\t1.0";
+        let options = ReaderOptions {
+            delimiter: b'\t',
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(2.0));
+    }
+
+    #[test]
+    fn test_get_content_without_headers() {
+        let data = "deposit, 1, 1, 3.0\ndeposit, 2, 2, 1.0\ndispute, 1, 1";
+        let options = ReaderOptions {
+            has_headers: false,
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+
+        assert_eq!(portfolio.stats().processed, 3);
+        assert_eq!(portfolio.get_account(1).unwrap().held, dec!(3.0));
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(1.0));
+    }
+
+    #[test]
+    fn test_transfer_column() {
+        let data = "\
+type, client, tx, amount, to
+deposit, 1, 1, 3.0
+transfer, 1, 2, 1.0, 2
+transfer, 1, 3, 1.0
+dispute, 1, 1";
+        let mut parsed = transactions(data.as_bytes());
+
+        assert_eq!(
+            parsed.nth(1),
+            Some(Transaction::create_transfer(1, 2, 2, dec!(1.0)))
+        );
+        assert!(matches!(
+            parsed.next(),
+            Some(Err(TransactionError::InvalidRow { reason, .. }))
+                if *reason == TransactionError::FieldCount { expected: 5, found: 4 }
+        ));
+        assert_eq!(parsed.next(), Some(Transaction::create_dispute(1, 1)));
+
+        let options = ReaderOptions {
+            has_headers: false,
+            ..Default::default()
+        };
+        let data = "deposit, 1, 1, 3.0\ntransfer, 1, 2, 1.0, 2";
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+        assert_eq!(portfolio.get_account(1).unwrap().total, dec!(2.0));
+        assert_eq!(portfolio.get_account(2).unwrap().total, dec!(1.0));
+    }
+
+    #[test]
+    fn test_skip_zero_amounts() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 5.0
+deposit, 1, 2, 0
+dispute, 1, 2
+withdrawal, 2, 3, 0.0";
+        let options = ReaderOptions {
+            skip_zero_amounts: true,
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(data.as_bytes());
+        let portfolio = get_content_with(&mut rdr, Policy::default(), &options).unwrap();
+
+        assert_eq!(portfolio.stats().processed, 2);
+        assert_eq!(portfolio.stats().skipped, 2);
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(5.0));
+        assert_eq!(s.held, dec!(0));
+        assert_eq!(portfolio.get_account(2), None);
+
+        // By default the zero deposit is kept and can be disputed
+        let mut rdr = reader_builder(&ReaderOptions::default()).from_reader(data.as_bytes());
+        let portfolio = get_content(&mut rdr).unwrap();
+        assert_eq!(portfolio.disputed_tx_ids(1), vec![2]);
+        assert!(portfolio.get_account(2).is_some());
+    }
+
+    #[test]
+    fn test_decimal_comma_requires_other_delimiter() {
+        let options = ReaderOptions {
+            delimiter: b',',
+            decimal_comma: true,
+            ..Default::default()
+        };
+        let mut rdr = reader_builder(&options).from_reader(DATA.as_bytes());
+        assert_eq!(
+            get_content_with(&mut rdr, Policy::default(), &options).unwrap_err(),
+            TransactionError::Parse(String::from(
+                "Decimal comma requires a delimiter other than ','"
+            ))
+        );
+    }
+
+    fn spawn_controlled(
+        control: &ProcessingControl,
+    ) -> thread::JoinHandle<Result<Portfolio, TransactionError>> {
+        let control = control.clone();
+        thread::spawn(move || {
+            let mut rdr = ReaderBuilder::new()
+                .flexible(true)
+                .trim(Trim::All)
+                .from_reader(DATA.as_bytes());
+            get_content_controlled(
+                &mut rdr,
+                Policy::default(),
+                &ReaderOptions::default(),
+                &control,
+            )
+        })
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let control = ProcessingControl::default();
+        control.pause.store(true, Ordering::SeqCst);
+
+        let handle = spawn_controlled(&control);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+
+        control.pause.store(false, Ordering::SeqCst);
+        let portfolio = handle.join().unwrap().unwrap();
+        assert_eq!(portfolio.stats().processed, 9);
+    }
+
+    #[test]
+    fn test_stop_while_paused() {
+        let control = ProcessingControl::default();
+        control.pause.store(true, Ordering::SeqCst);
+
+        let handle = spawn_controlled(&control);
+        thread::sleep(Duration::from_millis(20));
+        control.stop.store(true, Ordering::SeqCst);
+
+        let portfolio = handle.join().unwrap().unwrap();
+        assert_eq!(portfolio.stats().processed, 0);
+        assert!(portfolio.snapshots().next().is_none());
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let path = std::env::temp_dir().join(format!(
+            "transactions_checkpoint_{}.json",
+            std::process::id()
+        ));
+        let checkpoints = CheckpointOptions {
+            every: Some(4),
+            path: path.clone(),
+        };
+        let start = Checkpoint {
+            rows: 0,
+            portfolio: Portfolio::new(),
+        };
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(DATA.as_bytes());
+        let full = get_content_from(
+            &mut rdr,
+            start,
+            &ReaderOptions::default(),
+            &ProcessingControl::default(),
+            &checkpoints,
+        )
+        .unwrap();
+
+        // The last checkpoint was taken after 8 of the 9 records
+        let checkpoint = load_checkpoint(&path).unwrap();
+        let resume_from = load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.rows, 8);
+
+        let first_rows = DATA.lines().take(9).collect::<Vec<&str>>().join("\n");
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(first_rows.as_bytes());
+        let expected = get_content(&mut rdr).unwrap();
+        let saved = checkpoint.portfolio;
+        assert!(saved.snapshots().eq(expected.snapshots()));
+
+        // Resuming from the checkpoint applies only the remaining record
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(DATA.as_bytes());
+        let resumed = get_content_from(
+            &mut rdr,
+            resume_from,
+            &ReaderOptions::default(),
+            &ProcessingControl::default(),
+            &CheckpointOptions::default(),
+        )
+        .unwrap();
+        assert!(resumed.snapshots().eq(full.snapshots()));
+        assert_eq!(resumed.stats().processed, 9);
+    }
+
+    #[test]
+    fn test_get_content_from_inputs() {
+        let day1 = "type, client, tx, amount\ndeposit, 1, 1, 3.0\ndeposit, 2, 2, 1.0";
+        let day2 = "type, client, tx, amount\ndispute, 1, 1\nchargeback, 1, 1";
+        let options = ReaderOptions::default();
+        let portfolio = get_content_from_inputs(
+            vec![day1.as_bytes(), day2.as_bytes()],
+            Checkpoint::default(),
+            &options,
+            &ProcessingControl::default(),
+            &CheckpointOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(portfolio.stats().processed, 4);
+        let s = portfolio.get_account(1).unwrap();
+        assert_eq!(s.total, dec!(0));
+        assert!(s.locked);
+
+        // Resuming after the first input skips all of its records
+        let start = Checkpoint {
+            rows: 2,
+            portfolio: Portfolio::new(),
+        };
+        let portfolio = get_content_from_inputs(
+            vec![day1.as_bytes(), day2.as_bytes()],
+            start,
+            &options,
+            &ProcessingControl::default(),
+            &CheckpointOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(portfolio.stats().processed, 2);
+        assert_eq!(portfolio.get_account(2), None);
+    }
+
+    #[test]
+    fn test_get_content_from_stdin() {
+        let input = select_input(Path::new(STDIN), Box::new(DATA.as_bytes())).unwrap();
+        let mut rdr = reader_builder(&ReaderOptions::default()).from_reader(input);
+        let portfolio = get_content(&mut rdr).unwrap();
+
+        assert_eq!(portfolio.stats().processed, 9);
+        let mut snapshots = portfolio.snapshots();
+        assert_eq!(snapshots.next().unwrap().total, dec!(1.5));
+        assert_eq!(snapshots.next().unwrap().total, dec!(2.0));
+    }
+
+    #[test]
+    fn test_gzipped_input() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let path =
+            std::env::temp_dir().join(format!("transactions_input_{}.csv.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(DATA.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let mut rdr = open_input(&path, &ReaderOptions::default()).unwrap();
+        let gzipped = get_content(&mut rdr).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut rdr = reader_builder(&ReaderOptions::default()).from_reader(DATA.as_bytes());
+        let plain = get_content(&mut rdr).unwrap();
+        assert_eq!(gzipped.stats(), plain.stats());
+        assert_eq!(
+            gzipped.snapshots().collect::<Vec<_>>(),
+            plain.snapshots().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_open_input_missing_file() {
+        assert!(matches!(
+            open_input(String::from("missing.csv"), &ReaderOptions::default()),
+            Err(TransactionError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_filenames_from_args() {
+        assert_eq!(
+            get_filenames(vec![String::from("bin"), String::from("filename.csv")]).unwrap(),
+            vec![String::from("filename.csv")]
+        );
+        assert_eq!(
+            get_filenames(vec![String::from("bin"), String::from("-")]).unwrap(),
+            vec![String::from(STDIN)]
+        );
+    }
+
+    #[test]
+    fn test_get_quoted_and_padded_filenames() {
+        let filenames = get_filenames(vec![
+            String::from("bin"),
+            String::from("\"tests/transactions.csv\""),
+            String::from(" tests/transactions.csv "),
+            String::from("' tests/day 1.csv'"),
+            String::from("\"unbalanced.csv'"),
+        ])
+        .unwrap();
+        assert_eq!(
+            filenames,
+            vec![
+                "tests/transactions.csv",
+                "tests/transactions.csv",
+                "tests/day 1.csv",
+                "\"unbalanced.csv'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_many_filenames() {
+        assert_eq!(
+            get_filenames(vec![
+                String::from("bin"),
+                String::from("day1.csv"),
+                String::from("day2.csv"),
+            ])
+            .unwrap(),
+            vec![String::from("day1.csv"), String::from("day2.csv")]
+        );
     }
 
     #[test]
     fn wrong_args_number_1() {
-        match get_filename(vec![String::from("bin")]) {
+        match get_filenames(vec![String::from("bin")]) {
             Err(err) => {
                 assert_eq!(err, "Wrong number of arguments")
             }
@@ -1,46 +1,745 @@
 use crate::domain::Snapshot;
 use csv::WriterBuilder;
-use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
-use serde::Serialize;
-
-#[derive(Debug, Serialize)]
-struct SnapshotRow {
-    client: u16,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
-}
-
-pub fn write_headers() {
-    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
-
-    let row = SnapshotRow {
-        client: 0,
-        total: dec!(0),
-        held: dec!(0),
-        available: dec!(0),
-        locked: false,
-    };
-    wtr.serialize(row).unwrap();
-    let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
-    let vec: Vec<&str> = data.split("\n").collect();
-    println!("{}", vec[0])
-}
-
-pub fn write(s: Snapshot) {
-    let row = SnapshotRow {
-        client: s.client,
-        total: s.total,
-        held: s.held,
-        available: s.get_available(),
-        locked: s.locked,
-    };
-
-    let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-    wtr.serialize(row).unwrap();
-
-    let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
-    print!("{}", data)
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::{error::Error, io, path::PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // One JSON object per line and account
+    JsonLines,
+    FixedWidth(ColumnWidths),
+}
+
+// Output settings. `client_offset` is added to every client id when writing,
+// so ids from several systems can be merged without colliding; it never
+// affects processing. `anomalies` adds an `anomaly` column to the CSV output
+// listing the flags returned by `Snapshot::anomalies`. Amounts are written with
+// exactly `scale` decimal places, e.g. 2 for cents or 8 for most crypto assets.
+// The report goes to the file at `path` when set, to stdout otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub client_offset: u32,
+    pub anomalies: bool,
+    pub scale: u32,
+    pub path: Option<PathBuf>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::default(),
+            client_offset: 0,
+            anomalies: false,
+            scale: 4,
+            path: None,
+        }
+    }
+}
+
+// Column widths for the fixed-width report. Ids and amounts are right-justified,
+// the currency, the locked flag and the anomalies are left-justified. Each
+// width must fit the column label as well as its values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnWidths {
+    pub client: usize,
+    pub currency: usize,
+    pub amount: usize,
+    pub locked: usize,
+    pub anomaly: usize,
+}
+
+impl Default for ColumnWidths {
+    fn default() -> Self {
+        Self {
+            client: 10,
+            currency: 8,
+            amount: 18,
+            locked: 6,
+            // Fits every flag `Snapshot::anomalies` can return at once
+            anomaly: 30,
+        }
+    }
+}
+
+const COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+const ANOMALY_COLUMN: &str = "anomaly";
+const CURRENCY_COLUMN: &str = "currency";
+
+// A snapshot as written to the CSV and JSON lines reports, rounded to the
+// output scale. The output columns are computed while serializing, so
+// `COLUMNS` is the only place the schema is spelled out. The `currency` column
+// follows the client id when `currency` is set, so inputs without currencies
+// keep the same report.
+struct SnapshotRecord<'a> {
+    snapshot: Snapshot,
+    client: u32,
+    currency: bool,
+    options: &'a OutputOptions,
+}
+
+impl<'a> SnapshotRecord<'a> {
+    fn new(
+        snapshot: &Snapshot,
+        currency: bool,
+        options: &'a OutputOptions,
+    ) -> Result<Self, &'static str> {
+        Ok(Self {
+            snapshot: snapshot.rounded(options.scale),
+            client: output_client_id(snapshot, options.client_offset)?,
+            currency,
+            options,
+        })
+    }
+}
+
+impl Serialize for SnapshotRecord<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let s = &self.snapshot;
+        let len = COLUMNS.len() + usize::from(self.currency) + usize::from(self.options.anomalies);
+        let mut row = serializer.serialize_struct("Snapshot", len)?;
+        row.serialize_field(COLUMNS[0], &self.client)?;
+        if self.currency {
+            row.serialize_field(CURRENCY_COLUMN, s.currency.as_deref().unwrap_or(""))?;
+        }
+        row.serialize_field(COLUMNS[1], &s.get_available())?;
+        row.serialize_field(COLUMNS[2], &s.held)?;
+        row.serialize_field(COLUMNS[3], &s.total)?;
+        row.serialize_field(COLUMNS[4], &s.locked)?;
+        if self.options.anomalies {
+            row.serialize_field(ANOMALY_COLUMN, &s.anomalies().join(","))?;
+        }
+        row.end()
+    }
+}
+
+// Writes the CSV header and one row per snapshot into `out` through a single
+// csv writer. The `currency` column is added when any snapshot has one.
+pub fn write_csv<W, I>(out: W, snapshots: I, options: &OutputOptions) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+    I: IntoIterator<Item = Snapshot>,
+{
+    let snapshots: Vec<Snapshot> = snapshots.into_iter().collect();
+    let currency = snapshots.iter().any(|s| s.currency.is_some());
+    let mut wtr = WriterBuilder::new().has_headers(false).from_writer(out);
+    wtr.write_record(csv_headers(currency, options))?;
+    for s in snapshots {
+        wtr.serialize(SnapshotRecord::new(&s, currency, options)?)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Writes one JSON object per snapshot into `out`, each on its own line, with
+// the same fields as the CSV columns, plus the currency of the snapshots that
+// have one. Amounts are strings so they keep their exact decimal places.
+pub fn write_jsonl<W, I>(
+    mut out: W,
+    snapshots: I,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+    I: IntoIterator<Item = Snapshot>,
+{
+    for s in snapshots {
+        let record = SnapshotRecord::new(&s, s.currency.is_some(), options)?;
+        serde_json::to_writer(&mut out, &record)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn csv_headers(currency: bool, options: &OutputOptions) -> Vec<&'static str> {
+    let mut headers = COLUMNS.to_vec();
+    if currency {
+        headers.insert(1, CURRENCY_COLUMN);
+    }
+    if options.anomalies {
+        headers.push(ANOMALY_COLUMN);
+    }
+    headers
+}
+
+fn output_client_id(s: &Snapshot, client_offset: u32) -> Result<u32, &'static str> {
+    s.client
+        .checked_add(client_offset)
+        .ok_or("Client id offset overflows the output id")
+}
+
+// Writes the fixed-width header and one line per snapshot into `out`, with
+// the same columns as `write_csv`. Labels are justified like the values
+// below them, so a width narrower than its label is an error.
+pub fn write_fixed_width<W, I>(
+    mut out: W,
+    snapshots: I,
+    widths: &ColumnWidths,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+    I: IntoIterator<Item = Snapshot>,
+{
+    let snapshots: Vec<Snapshot> = snapshots.into_iter().collect();
+    let currency = snapshots.iter().any(|s| s.currency.is_some());
+    let headers = csv_headers(currency, options);
+    writeln!(out, "{}", fixed_width_line(&headers, widths)?)?;
+    for s in snapshots {
+        writeln!(
+            out,
+            "{}",
+            format_fixed_width(&s, currency, widths, options)?
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn format_fixed_width(
+    s: &Snapshot,
+    currency: bool,
+    widths: &ColumnWidths,
+    options: &OutputOptions,
+) -> Result<String, &'static str> {
+    let client = output_client_id(s, options.client_offset)?;
+    let s = s.rounded(options.scale);
+
+    if widths.locked < "false".len() {
+        return Err("Value does not fit in column width");
+    }
+
+    let mut values = vec![client.to_string()];
+    if currency {
+        values.push(s.currency.clone().unwrap_or_default());
+    }
+    for amount in [s.get_available(), s.held, s.total] {
+        values.push(amount.to_string());
+    }
+    values.push(s.locked.to_string());
+    if options.anomalies {
+        values.push(s.anomalies().join(","));
+    }
+    let labels = csv_headers(currency, options);
+    let columns = labels.iter().zip(&values).map(|(label, value)| {
+        let (width, align) = fixed_width_column(label, widths);
+        justify(value, width, align)
+    });
+    columns.collect()
+}
+
+// Justifies each header label to the width of its column.
+fn fixed_width_line(labels: &[&str], widths: &ColumnWidths) -> Result<String, &'static str> {
+    labels
+        .iter()
+        .map(|label| {
+            let (width, align) = fixed_width_column(label, widths);
+            justify(label, width, align)
+        })
+        .collect()
+}
+
+fn fixed_width_column(label: &str, widths: &ColumnWidths) -> (usize, Align) {
+    match label {
+        CURRENCY_COLUMN => (widths.currency, Align::Left),
+        ANOMALY_COLUMN => (widths.anomaly, Align::Left),
+        "client" => (widths.client, Align::Right),
+        "locked" => (widths.locked, Align::Left),
+        _ => (widths.amount, Align::Right),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+}
+
+fn justify(value: &str, width: usize, align: Align) -> Result<String, &'static str> {
+    if value.len() > width {
+        return Err("Value does not fit in column width");
+    }
+    Ok(match align {
+        Align::Left => format!("{:<width$}", value),
+        Align::Right => format!("{:>width$}", value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn format_row(s: &Snapshot, options: &OutputOptions) -> Result<String, &'static str> {
+        let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        wtr.serialize(SnapshotRecord::new(s, s.currency.is_some(), options)?)
+            .unwrap();
+        Ok(String::from_utf8(wtr.into_inner().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_write_csv_into_buffer() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(1.5),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(3),
+                held: dec!(1),
+                locked: true,
+                currency: None,
+            },
+        ];
+
+        let mut out = vec![];
+        write_csv(&mut out, snapshots.clone(), &OutputOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n\
+             1,1.5000,0.0000,1.5000,false\n\
+             2,2.0000,1.0000,3.0000,true\n"
+        );
+
+        let options = OutputOptions {
+            anomalies: true,
+            ..Default::default()
+        };
+        let mut out = vec![];
+        write_csv(&mut out, snapshots, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,anomaly\n\
+             1,1.5000,0.0000,1.5000,false,\n\
+             2,2.0000,1.0000,3.0000,true,\"held,locked\"\n"
+        );
+    }
+
+    #[test]
+    fn test_currency_column() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(2),
+                held: dec!(0),
+                locked: false,
+                currency: Some(String::from("EUR")),
+            },
+            Snapshot {
+                client: 1,
+                total: dec!(1.5),
+                held: dec!(0),
+                locked: false,
+                currency: Some(String::from("USD")),
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(3),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+        ];
+
+        let mut out = vec![];
+        write_csv(&mut out, snapshots.clone(), &OutputOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,EUR,2.0000,0.0000,2.0000,false\n\
+             1,USD,1.5000,0.0000,1.5000,false\n\
+             2,,3.0000,0.0000,3.0000,false\n"
+        );
+
+        let mut out = vec![];
+        write_jsonl(&mut out, snapshots[1..].to_vec(), &OutputOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"client\":1,\"currency\":\"USD\",\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"3.0000\",\"held\":\"0.0000\",\"total\":\"3.0000\",\"locked\":false}\n"
+        );
+    }
+
+    // Every format terminates each line exactly once, the last one included.
+    #[test]
+    fn test_trailing_newline() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(1),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(2),
+                held: dec!(1),
+                locked: true,
+                currency: None,
+            },
+        ];
+        let options = OutputOptions::default();
+
+        let mut out = vec![];
+        write_csv(&mut out, snapshots.clone(), &options).unwrap();
+        assert_eq!(
+            out,
+            b"client,available,held,total,locked\n\
+              1,1.0000,0.0000,1.0000,false\n\
+              2,1.0000,1.0000,2.0000,true\n"
+        );
+
+        let mut jsonl = vec![];
+        write_jsonl(&mut jsonl, snapshots.clone(), &options).unwrap();
+        let mut fixed = vec![];
+        write_fixed_width(&mut fixed, snapshots, &ColumnWidths::default(), &options).unwrap();
+        for (out, lines) in [(jsonl, 2), (fixed, 3)] {
+            assert_eq!(out.iter().filter(|b| **b == b'\n').count(), lines);
+            assert!(out.ends_with(b"\n") && !out.ends_with(b"\n\n"));
+        }
+    }
+
+    #[test]
+    fn test_write_csv_without_snapshots() {
+        let mut out = vec![];
+        write_csv(&mut out, vec![], &OutputOptions::default()).unwrap();
+        assert_eq!(out, b"client,available,held,total,locked\n");
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(1.5),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(3),
+                held: dec!(1),
+                locked: true,
+                currency: None,
+            },
+        ];
+
+        let mut out = vec![];
+        write_jsonl(&mut out, snapshots, &OutputOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"2.0000\",\"held\":\"1.0000\",\"total\":\"3.0000\",\"locked\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_fixed_width() {
+        let widths = ColumnWidths::default();
+        let s = Snapshot {
+            client: 42,
+            total: dec!(10.5),
+            held: dec!(2),
+            locked: true,
+            currency: None,
+        };
+
+        let line = format_fixed_width(&s, false, &widths, &OutputOptions::default()).unwrap();
+        assert_eq!(line.len(), 10 + 18 * 3 + 6);
+        assert_eq!(&line[..10], "        42");
+        assert_eq!(&line[10..28], "            8.5000");
+        assert_eq!(&line[28..46], "            2.0000");
+        assert_eq!(&line[46..64], "           10.5000");
+        assert_eq!(&line[64..], "true  ");
+    }
+
+    #[test]
+    fn test_write_fixed_width_into_buffer() {
+        let widths = ColumnWidths {
+            client: 6,
+            amount: 10,
+            locked: 6,
+            ..Default::default()
+        };
+        let s = Snapshot {
+            client: 7,
+            total: dec!(2),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        let mut out = vec![];
+        write_fixed_width(&mut out, vec![s], &widths, &OutputOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "client available      held     totallocked\n     7    2.0000    0.0000    2.0000false \n"
+        );
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0].len(), lines[1].len());
+    }
+
+    #[test]
+    fn test_fixed_width_narrower_than_label() {
+        let widths = ColumnWidths {
+            amount: 8,
+            ..Default::default()
+        };
+        let mut out = vec![];
+        assert!(write_fixed_width(&mut out, vec![], &widths, &OutputOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_fixed_width_anomaly_column() {
+        let widths = ColumnWidths {
+            client: 6,
+            amount: 10,
+            locked: 6,
+            ..Default::default()
+        };
+        let s = Snapshot {
+            client: 3,
+            total: dec!(1),
+            held: dec!(2),
+            locked: true,
+            currency: None,
+        };
+        let options = OutputOptions {
+            anomalies: true,
+            ..Default::default()
+        };
+
+        let mut out = vec![];
+        write_fixed_width(&mut out, vec![s], &widths, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client available      held     totallockedanomaly                       \n     \
+                  3   -1.0000    2.0000    1.0000true  negative_available,held,locked\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width_currency_column() {
+        let snapshots = vec![
+            Snapshot {
+                client: 1,
+                total: dec!(2),
+                held: dec!(0),
+                locked: false,
+                currency: Some(String::from("EUR")),
+            },
+            Snapshot {
+                client: 1,
+                total: dec!(3),
+                held: dec!(0),
+                locked: false,
+                currency: Some(String::from("USD")),
+            },
+        ];
+        let widths = ColumnWidths {
+            client: 6,
+            currency: 8,
+            amount: 10,
+            locked: 6,
+            ..Default::default()
+        };
+
+        let mut out = vec![];
+        write_fixed_width(&mut out, snapshots, &widths, &OutputOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "clientcurrency available      held     totallocked\n     \
+                  1EUR         2.0000    0.0000    2.0000false \n     \
+                  1USD         3.0000    0.0000    3.0000false \n"
+        );
+    }
+
+    #[test]
+    fn test_format_fixed_width_overflow() {
+        let widths = ColumnWidths {
+            client: 2,
+            ..Default::default()
+        };
+        let s = Snapshot {
+            client: 420,
+            total: dec!(0),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        assert_eq!(
+            format_fixed_width(&s, false, &widths, &OutputOptions::default()).unwrap_err(),
+            "Value does not fit in column width"
+        );
+    }
+
+    #[test]
+    fn test_client_offset() {
+        let s = Snapshot {
+            client: 42,
+            total: dec!(1),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        let options = OutputOptions {
+            client_offset: 100000,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_row(&s, &options).unwrap(),
+            "100042,1.0000,0.0000,1.0000,false\n"
+        );
+        let options = OutputOptions::default();
+        assert_eq!(
+            format_row(&s, &options).unwrap(),
+            "42,1.0000,0.0000,1.0000,false\n"
+        );
+        assert_eq!(s.client, 42);
+    }
+
+    #[test]
+    fn test_client_offset_overflow() {
+        let s = Snapshot {
+            client: 42,
+            total: dec!(1),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        assert_eq!(
+            format_row(
+                &s,
+                &OutputOptions {
+                    client_offset: u32::MAX,
+                    ..Default::default()
+                }
+            )
+            .unwrap_err(),
+            "Client id offset overflows the output id"
+        );
+    }
+
+    #[test]
+    fn test_anomaly_column() {
+        let options = OutputOptions {
+            anomalies: true,
+            ..Default::default()
+        };
+        let negative = Snapshot {
+            client: 1,
+            total: dec!(-2),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+        let locked = Snapshot {
+            client: 2,
+            total: dec!(3),
+            held: dec!(1),
+            locked: true,
+            currency: None,
+        };
+        let clean = Snapshot {
+            client: 3,
+            total: dec!(3),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        assert_eq!(
+            format_row(&negative, &options).unwrap(),
+            "1,-2.0000,0.0000,-2.0000,false,negative_available\n"
+        );
+        assert_eq!(
+            format_row(&locked, &options).unwrap(),
+            "2,2.0000,1.0000,3.0000,true,\"held,locked\"\n"
+        );
+        assert_eq!(
+            format_row(&clean, &options).unwrap(),
+            "3,3.0000,0.0000,3.0000,false,\n"
+        );
+    }
+
+    #[test]
+    fn test_amounts_have_four_decimal_places() {
+        let whole = Snapshot {
+            client: 1,
+            total: dec!(1.0),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+        let fraction = Snapshot {
+            client: 2,
+            total: dec!(0.12345),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        assert_eq!(
+            format_row(&whole, &OutputOptions::default()).unwrap(),
+            "1,1.0000,0.0000,1.0000,false\n"
+        );
+        assert_eq!(
+            format_row(&fraction, &OutputOptions::default()).unwrap(),
+            "2,0.1235,0.0000,0.1235,false\n"
+        );
+    }
+
+    #[test]
+    fn test_rounded_columns_add_up() {
+        let s = Snapshot {
+            client: 1,
+            total: dec!(0.0001),
+            held: dec!(0.00005),
+            locked: false,
+            currency: None,
+        };
+
+        assert_eq!(
+            format_row(&s, &OutputOptions::default()).unwrap(),
+            "1,0.0000,0.0001,0.0001,false\n"
+        );
+    }
+
+    #[test]
+    fn test_configured_scale() {
+        let s = Snapshot {
+            client: 1,
+            total: dec!(0.125),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        };
+
+        let cents = OutputOptions {
+            scale: 2,
+            ..Default::default()
+        };
+        assert_eq!(format_row(&s, &cents).unwrap(), "1,0.13,0.00,0.13,false\n");
+        let crypto = OutputOptions {
+            scale: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_row(&s, &crypto).unwrap(),
+            "1,0.12500000,0.00000000,0.12500000,false\n"
+        );
+    }
 }
@@ -1,6 +1,394 @@
-use transactions_handler;
+use rust_decimal_macros::dec;
+use transactions_handler::checkpoint::Checkpoint;
+use transactions_handler::error::TransactionError;
+use transactions_handler::reader::ReaderOptions;
+use transactions_handler::writer::{write_csv, OutputFormat, OutputOptions};
+use transactions_handler::{process_with_config, run_with_config, write_result, EngineConfig};
+use transactions_handler::{
+    Overdraw, Policy, Portfolio, Transaction, TransactionEngine, WithdrawalDisputes,
+};
+use transactions_handler::{ProcessingStats, RunResult, Snapshot};
+
+// Processes `filenames` in order with the default configuration
+fn process(filenames: &[&str]) -> Result<RunResult, TransactionError> {
+    process_with_config(&EngineConfig::default(), filenames, Checkpoint::default())
+}
 
 #[test]
 fn test_run() {
-    transactions_handler::run(String::from("tests/transactions.csv"));
+    transactions_handler::run(String::from("tests/transactions.csv")).unwrap();
+    transactions_handler::run("tests/transactions.csv").unwrap();
+    transactions_handler::run(std::path::Path::new("tests/transactions.csv")).unwrap();
+}
+
+#[test]
+fn test_process_with_config() {
+    let result = process(&["tests/transactions.csv"]).unwrap();
+
+    assert_eq!(
+        result.snapshots,
+        vec![
+            Snapshot {
+                client: 1,
+                total: dec!(1.5),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(2),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+        ]
+    );
+    assert_eq!(
+        result.stats,
+        ProcessingStats {
+            processed: 9,
+            rejected: 3,
+            skipped: 0,
+            pending: 0,
+        }
+    );
+    // The dispute of tx 3 would leave client 1 with negative available funds,
+    // so there is nothing to charge back
+    let reasons: Vec<(u32, TransactionError)> = result
+        .rejections
+        .into_iter()
+        .map(|r| (r.tx, r.reason))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            (3, TransactionError::InsufficientFunds),
+            (5, TransactionError::InsufficientFunds),
+            (3, TransactionError::NotDisputed(3)),
+        ]
+    );
+}
+
+#[test]
+fn test_process() {
+    let snapshots = process(&["tests/transactions.csv"]).unwrap().snapshots;
+
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[0].client, 1);
+    assert_eq!(snapshots[0].get_available(), dec!(1.5));
+    assert_eq!(snapshots[1].client, 2);
+    assert_eq!(snapshots[1].get_available(), dec!(2.0));
+}
+
+#[test]
+fn test_process_with_policy() {
+    let config = EngineConfig {
+        policy: Policy {
+            overdraw_policy: Overdraw::Allow,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let result =
+        process_with_config(&config, ["tests/transactions.csv"], Checkpoint::default()).unwrap();
+    let default = process(&["tests/transactions.csv"]).unwrap();
+    assert_eq!(result.stats.rejected, default.stats.rejected - 1);
+    assert_eq!(result.snapshots[1].total, dec!(-1.0));
+}
+
+#[test]
+fn test_process_missing_file() {
+    let result = process(&["tests/missing.csv"]);
+    assert!(matches!(result, Err(TransactionError::Io(_))));
+}
+
+#[test]
+fn test_snapshots_sorted_by_client() {
+    let snapshots = process(&["tests/unordered.csv"]).unwrap().snapshots;
+
+    let clients: Vec<u32> = snapshots.iter().map(|s| s.client).collect();
+    assert_eq!(clients, vec![1, 2, 3]);
+    assert_eq!(snapshots[2].total, dec!(4.0));
+}
+
+#[test]
+fn test_run_with_output_file() {
+    let path = std::env::temp_dir().join(format!("transactions_output_{}.csv", std::process::id()));
+    let options = transactions_handler::writer::OutputOptions {
+        path: Some(path.clone()),
+        ..Default::default()
+    };
+    let result = process(&["tests/transactions.csv"]).unwrap();
+    write_result(result, &options).unwrap();
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        written,
+        "client,available,held,total,locked\n\
+         1,1.5000,0.0000,1.5000,false\n\
+         2,2.0000,0.0000,2.0000,false\n"
+    );
+}
+
+#[test]
+fn test_stats_for_mixed_input() {
+    let result = process(&["tests/mixed.csv"]).unwrap();
+
+    // The `transfer` without a destination field and the withdrawal without amount are
+    // skipped.
+    // The reused tx 1, the overdraft, the deposit on the locked account and the
+    // resolve without dispute are rejected
+    assert_eq!(
+        result.stats,
+        ProcessingStats {
+            processed: 8,
+            rejected: 4,
+            skipped: 2,
+            pending: 0,
+        }
+    );
+    assert_eq!(result.stats.applied(), 4);
+}
+
+#[test]
+fn test_run_many_files() {
+    let (day1, day2) = ("tests/day1.csv", "tests/day2.csv");
+
+    // The disputes of the second day refer to deposits of the first one
+    let result = process(&[day1, day2]).unwrap();
+    assert_eq!(
+        result.snapshots,
+        vec![
+            Snapshot {
+                client: 1,
+                total: dec!(0),
+                held: dec!(0),
+                locked: true,
+                currency: None,
+            },
+            Snapshot {
+                client: 2,
+                total: dec!(2),
+                held: dec!(0),
+                locked: false,
+                currency: None,
+            },
+        ]
+    );
+
+    let first = process(&[day1]).unwrap();
+    assert_eq!(first.snapshots[0].total, dec!(5));
+    assert!(!first.snapshots[0].locked);
+    let second = process(&[day2]).unwrap();
+    assert_eq!(second.stats.rejected, 2);
+    assert_eq!(second.snapshots[0].total, dec!(0));
+    assert!(!second.snapshots[0].locked);
+}
+
+#[test]
+fn test_withdrawal_disputes_policy() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 withdrawal,1,2,4\n\
+                 dispute,1,2";
+    let run = |withdrawal_disputes| {
+        let config = EngineConfig {
+            policy: Policy {
+                withdrawal_disputes,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut output = vec![];
+        run_with_config(&config, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        run(WithdrawalDisputes::CreditAndHold),
+        "client,available,held,total,locked\n1,6.0000,4.0000,10.0000,false\n"
+    );
+    assert_eq!(
+        run(WithdrawalDisputes::HoldOnly),
+        "client,available,held,total,locked\n1,2.0000,4.0000,6.0000,false\n"
+    );
+}
+
+#[test]
+fn test_portfolio_dry_run() {
+    let mut portfolio = Portfolio::new();
+    portfolio
+        .add_transaction(Transaction::create_deposit(1, 1, dec!(5)).unwrap())
+        .unwrap();
+
+    let fits = Transaction::create_withdraw(1, 2, dec!(2)).unwrap();
+    assert_eq!(portfolio.dry_run(&fits).unwrap().total, dec!(3));
+    let overdraws = Transaction::create_withdraw(1, 2, dec!(8)).unwrap();
+    assert_eq!(
+        portfolio.dry_run(&overdraws),
+        Err(TransactionError::InsufficientFunds)
+    );
+    assert_eq!(portfolio.get_account(1).unwrap().total, dec!(5));
+}
+
+#[test]
+fn test_get_account() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .apply(Transaction::create_deposit(4, 1, dec!(2.5)).unwrap())
+        .unwrap();
+    assert_eq!(
+        engine.get_account(4),
+        Some(Snapshot {
+            client: 4,
+            total: dec!(2.5),
+            held: dec!(0),
+            locked: false,
+            currency: None,
+        })
+    );
+    assert_eq!(engine.get_account(5), None);
+}
+
+#[test]
+fn test_merge_engines() {
+    let mut left = TransactionEngine::new();
+    left.apply(Transaction::create_deposit(1, 1, dec!(3)).unwrap())
+        .unwrap();
+    let mut right = TransactionEngine::new();
+    right
+        .apply(Transaction::create_deposit(2, 2, dec!(4)).unwrap())
+        .unwrap();
+
+    left.merge(right).unwrap();
+    assert_eq!(left.client_ids(), vec![1, 2]);
+    assert_eq!(left.stats().processed, 2);
+
+    let mut other = TransactionEngine::new();
+    other
+        .apply(Transaction::create_deposit(1, 3, dec!(1)).unwrap())
+        .unwrap();
+    assert_eq!(left.merge(other), Err(TransactionError::DuplicateClient(1)));
+}
+
+#[test]
+fn test_save_and_load_engine_state() {
+    let transactions = [
+        Transaction::create_deposit(1, 1, dec!(10)).unwrap(),
+        Transaction::create_dispute(1, 2).unwrap(),
+        Transaction::create_deposit(1, 2, dec!(4)).unwrap(),
+        Transaction::create_withdraw(1, 3, dec!(3)).unwrap(),
+    ];
+    let mut uninterrupted = TransactionEngine::new();
+    for t in transactions.iter().cloned() {
+        uninterrupted.apply(t).unwrap();
+    }
+
+    let mut first = TransactionEngine::new();
+    for t in transactions[..2].iter().cloned() {
+        first.apply(t).unwrap();
+    }
+    let mut state = vec![];
+    first.save_state(&mut state).unwrap();
+    let mut resumed = TransactionEngine::load_state(state.as_slice()).unwrap();
+    for t in transactions[2..].iter().cloned() {
+        resumed.apply(t).unwrap();
+    }
+
+    assert_eq!(resumed.result(), uninterrupted.result());
+    assert_eq!(resumed.get_account(1).unwrap().held, dec!(4));
+}
+
+#[test]
+fn test_clear_all_disputes() {
+    let mut engine = TransactionEngine::new();
+    for t in [
+        Transaction::create_deposit(1, 1, dec!(5)).unwrap(),
+        Transaction::create_dispute(1, 1).unwrap(),
+    ] {
+        engine.apply(t).unwrap();
+    }
+    engine.clear_all_disputes().unwrap();
+
+    let s = engine.get_account(1).unwrap();
+    assert_eq!((s.total, s.held), (dec!(5), dec!(0)));
+}
+
+#[test]
+fn test_run_with_config() {
+    let config = EngineConfig {
+        reader: ReaderOptions {
+            delimiter: b'\t',
+            decimal_comma: true,
+            ..Default::default()
+        },
+        output: OutputOptions {
+            scale: 2,
+            format: OutputFormat::JsonLines,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let input = "type\tclient\ttx\tamount\n\
+                 deposit\t1\t1\t10,555\n\
+                 withdrawal\t1\t2\t20,0\n\
+                 deposit\t2\t3\t1,5";
+    let mut output = vec![];
+    let stats = run_with_config(&config, input.as_bytes(), &mut output).unwrap();
+
+    assert_eq!(stats.processed, 3);
+    assert_eq!(stats.rejected, 1);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "{\"client\":1,\"available\":\"10.56\",\"held\":\"0.00\",\"total\":\"10.56\",\"locked\":false}\n\
+         {\"client\":2,\"available\":\"1.50\",\"held\":\"0.00\",\"total\":\"1.50\",\"locked\":false}\n"
+    );
+}
+
+#[test]
+fn test_run_without_transactions() {
+    for filename in ["tests/header_only.csv", "tests/empty.csv"] {
+        let result = process(&[filename]).unwrap();
+        assert!(result.snapshots.is_empty());
+        assert_eq!(result.stats, ProcessingStats::default());
+        assert_eq!(
+            transactions_handler::validate(filename).unwrap(),
+            ProcessingStats::default()
+        );
+
+        let parallel = transactions_handler::run_parallel(filename, 4).unwrap();
+        assert_eq!(parallel, result);
+
+        let input = std::fs::read(filename).unwrap();
+        let mut output = vec![];
+        run_with_config(&EngineConfig::default(), input.as_slice(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+    }
+}
+
+// Each `tests/golden/<name>.csv` input must produce exactly the balances in
+// `tests/golden/<name>.expected.csv`
+#[test]
+fn test_golden_files() {
+    for name in [
+        "deposits_withdrawals",
+        "dispute_resolve",
+        "dispute_chargeback",
+        "locked_account",
+        "out_of_order",
+    ] {
+        let filename = format!("tests/golden/{name}.csv");
+        let snapshots = process(&[&filename]).unwrap().snapshots;
+        let mut output = vec![];
+        write_csv(&mut output, snapshots, &OutputOptions::default()).unwrap();
+
+        let expected =
+            std::fs::read_to_string(format!("tests/golden/{name}.expected.csv")).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected, "{name}");
+    }
 }